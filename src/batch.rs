@@ -0,0 +1,94 @@
+use std::panic::AssertUnwindSafe;
+use std::path::{Path, PathBuf};
+
+use rayon::prelude::*;
+
+use crate::{EncodeOptions, Error, JxlEncoder, Result, encode_dynamic_image};
+
+/// Result of encoding a file with [`batch_encode`].
+#[derive(Debug)]
+pub struct BatchResult {
+    pub input: PathBuf,
+    /// Encoded image, or the error encountered while reading or encoding the file.
+    pub result: Result<Vec<u8>>,
+    /// Whether the input is a JPEG file transcoded losslessly.
+    pub is_transcoded: bool,
+}
+
+/// Encodes image files concurrently in the rayon thread pool, and returns the results in the
+/// order of `paths`.
+///
+/// JPEG files are transcoded losslessly, falling back to encoding pixels if transcoding fails.
+/// Other files are decoded with the `image` crate and encoded with
+/// [`encode_dynamic_image`]. `progress` is called from worker threads as each file is done.
+/// A file which fails to encode doesn't affect other files; panics while encoding a file are
+/// reported as [`Error::Panicked`].
+pub fn batch_encode<P>(
+    paths: &[P],
+    options: &EncodeOptions,
+    progress: impl Fn(&BatchResult) + Sync,
+) -> Vec<BatchResult>
+where
+    P: AsRef<Path> + Sync,
+{
+    paths
+        .par_iter()
+        .map(|path| {
+            let input = path.as_ref();
+            // Decoders of the `image` crate may panic on malformed input.
+            let result = std::panic::catch_unwind(AssertUnwindSafe(|| encode_file(input, options)))
+                .unwrap_or(Err(Error::Panicked));
+            let (result, is_transcoded) = match result {
+                Ok((output, is_transcoded)) => (Ok(output), is_transcoded),
+                Err(err) => (Err(err), false),
+            };
+            let result = BatchResult {
+                input: input.to_owned(),
+                result,
+                is_transcoded,
+            };
+            progress(&result);
+            result
+        })
+        .collect()
+}
+
+fn encode_file(path: &Path, options: &EncodeOptions) -> Result<(Vec<u8>, bool)> {
+    let buffer = std::fs::read(path)?;
+    let format = image::guess_format(&buffer).map_err(from_image_error)?;
+    if format == image::ImageFormat::Jpeg
+        && let Ok(output) = transcode_jpeg(&buffer, options)
+    {
+        return Ok((output, true));
+    }
+
+    let image = image::load_from_memory_with_format(&buffer, format).map_err(from_image_error)?;
+    Ok((encode_dynamic_image(&image, options)?, false))
+}
+
+fn transcode_jpeg(jpeg: &[u8], options: &EncodeOptions) -> Result<Vec<u8>> {
+    let mut encoder = JxlEncoder::new().ok_or(Error::OutOfMemory)?;
+    encoder.set_jpeg_reconstruction(true)?;
+
+    // Distance doesn't apply to transcoded images.
+    let settings = encoder.create_frame_settings_with(|settings| {
+        settings.effort(options.effort);
+        Ok(())
+    })?;
+    encoder.add_frame(settings)?.jpeg(jpeg)?;
+    encoder.close_input();
+
+    let mut output = Vec::new();
+    for chunk in encoder.output_chunks() {
+        output.extend_from_slice(&chunk?);
+    }
+    Ok(output)
+}
+
+fn from_image_error(err: image::ImageError) -> Error {
+    match err {
+        image::ImageError::IoError(err) => Error::Io(err),
+        image::ImageError::Unsupported(_) => Error::NotSupported,
+        _ => Error::BadInput,
+    }
+}
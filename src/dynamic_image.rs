@@ -0,0 +1,142 @@
+use image::{ColorType, DynamicImage};
+
+use crate::{
+    BasicInfo, ColorEncoding, EncodeOptions, Error, JxlDecoder, JxlEncoder, RenderingIntent,
+    Result, SampleFormat,
+};
+
+/// Returns the number of channels and sample format of pixel buffers with the color type.
+///
+/// Returns `None` if libjxl doesn't accept the color type.
+pub fn color_type_format(color_type: ColorType) -> Option<(u32, SampleFormat)> {
+    let sample_format = match color_type {
+        ColorType::L8 | ColorType::La8 | ColorType::Rgb8 | ColorType::Rgba8 => SampleFormat::U8,
+        ColorType::L16 | ColorType::La16 | ColorType::Rgb16 | ColorType::Rgba16 => {
+            SampleFormat::U16
+        }
+        ColorType::Rgb32F | ColorType::Rgba32F => SampleFormat::F32,
+        _ => return None,
+    };
+    Some((color_type.channel_count() as u32, sample_format))
+}
+
+/// Encodes the image as a single frame JPEG XL image.
+///
+/// Color channels are assumed to be in sRGB, or linear sRGB for floating point images, as in
+/// the `image` crate. Returns [`Error::NotSupported`] if the color type isn't supported.
+pub fn encode_dynamic_image(image: &DynamicImage, options: &EncodeOptions) -> Result<Vec<u8>> {
+    let color_type = image.color();
+    let (num_channels, sample_format) = color_type_format(color_type).ok_or(Error::NotSupported)?;
+    let is_gray = !color_type.has_color();
+    let bits_per_sample = sample_format.bytes_per_sample() as u32 * 8;
+
+    let mut encoder = JxlEncoder::new().ok_or(Error::OutOfMemory)?;
+
+    let mut basic_info = BasicInfo::new();
+    basic_info.xsize = image.width();
+    basic_info.ysize = image.height();
+    basic_info.bits_per_sample = bits_per_sample;
+    basic_info.set_num_color_channels(if is_gray { 1 } else { 3 })?;
+    basic_info.set_uses_original_profile(options.is_lossless());
+    if color_type.has_alpha() {
+        basic_info.set_alpha(bits_per_sample, false);
+    }
+    if let Some((bits_per_sample, exponent_bits)) = sample_format.float_depth() {
+        basic_info.set_float_depth(bits_per_sample, exponent_bits)?;
+    }
+    encoder.set_basic_info(&basic_info)?;
+
+    let intent = RenderingIntent::Relative;
+    let color_encoding = match (is_gray, sample_format == SampleFormat::F32) {
+        (false, false) => ColorEncoding::srgb(intent),
+        (false, true) => ColorEncoding::srgb_linear(intent),
+        (true, false) => ColorEncoding::srgb_gray(intent),
+        (true, true) => ColorEncoding::srgb_gray_linear(intent),
+    };
+    encoder.set_color_encoding(&color_encoding)?;
+
+    let settings = encoder.create_frame_settings_with(|settings| options.apply(settings))?;
+    encoder
+        .add_frame(settings)?
+        .color_channels(num_channels, sample_format, image.as_bytes())?;
+    encoder.close_input();
+
+    let mut output = Vec::new();
+    for chunk in encoder.output_chunks() {
+        output.extend_from_slice(&chunk?);
+    }
+    Ok(output)
+}
+
+/// Decodes the image to a [`DynamicImage`], with the color type closest to the bit depth of it.
+///
+/// Pixels are in the color space libjxl decodes to without color management, and orientation
+/// is not applied; it's available in [`JxlDecoder::basic_info`]. Floating point grayscale
+/// images are decoded to RGB, as there's no floating point grayscale [`DynamicImage`].
+pub fn decode_to_dynamic_image(input: &[u8]) -> Result<DynamicImage> {
+    let mut decoder = JxlDecoder::new().ok_or(Error::OutOfMemory)?;
+    let basic_info = decoder.basic_info(input)?;
+    let (width, height) = (basic_info.xsize, basic_info.ysize);
+    let is_gray = basic_info.num_color_channels == 1;
+    let has_alpha = basic_info.alpha_bits != 0;
+
+    let sample_format = if basic_info.exponent_bits_per_sample > 0 {
+        SampleFormat::F32
+    } else if basic_info.bits_per_sample <= 8 {
+        SampleFormat::U8
+    } else {
+        SampleFormat::U16
+    };
+    let num_color_channels = if is_gray && sample_format != SampleFormat::F32 {
+        1
+    } else {
+        3
+    };
+    let num_channels = num_color_channels + has_alpha as u32;
+
+    let pixels = decoder.decode_to_pixels(input, num_channels, sample_format)?;
+    let expected =
+        width as usize * height as usize * num_channels as usize * sample_format.bytes_per_sample();
+    let got = pixels.len();
+    if got != expected {
+        return Err(Error::BufferSizeMismatch { expected, got });
+    }
+
+    let image = match sample_format {
+        SampleFormat::U8 => match num_channels {
+            1 => image::GrayImage::from_raw(width, height, pixels).map(DynamicImage::from),
+            2 => image::GrayAlphaImage::from_raw(width, height, pixels).map(DynamicImage::from),
+            3 => image::RgbImage::from_raw(width, height, pixels).map(DynamicImage::from),
+            _ => image::RgbaImage::from_raw(width, height, pixels).map(DynamicImage::from),
+        },
+        SampleFormat::U16 => {
+            let pixels = pixels
+                .chunks_exact(2)
+                .map(|sample| u16::from_ne_bytes([sample[0], sample[1]]))
+                .collect::<Vec<_>>();
+            match num_channels {
+                1 => image::ImageBuffer::<image::Luma<u16>, _>::from_raw(width, height, pixels)
+                    .map(DynamicImage::from),
+                2 => image::ImageBuffer::<image::LumaA<u16>, _>::from_raw(width, height, pixels)
+                    .map(DynamicImage::from),
+                3 => image::ImageBuffer::<image::Rgb<u16>, _>::from_raw(width, height, pixels)
+                    .map(DynamicImage::from),
+                _ => image::ImageBuffer::<image::Rgba<u16>, _>::from_raw(width, height, pixels)
+                    .map(DynamicImage::from),
+            }
+        }
+        SampleFormat::F16 | SampleFormat::F32 => {
+            let pixels = pixels
+                .chunks_exact(4)
+                .map(|sample| f32::from_ne_bytes([sample[0], sample[1], sample[2], sample[3]]))
+                .collect::<Vec<_>>();
+            if has_alpha {
+                image::Rgba32FImage::from_raw(width, height, pixels).map(DynamicImage::from)
+            } else {
+                image::Rgb32FImage::from_raw(width, height, pixels).map(DynamicImage::from)
+            }
+        }
+    };
+
+    image.ok_or(Error::BufferSizeMismatch { expected, got })
+}
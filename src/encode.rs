@@ -0,0 +1,175 @@
+use crate::{
+    BasicInfo, ColorEncoding, Effort, Error, FrameSettings, JxlDecoder, JxlEncoder, MetadataBox,
+    RenderingIntent, Result, SampleFormat,
+};
+
+/// Box types which libjxl writes from APP markers of a JPEG when transcoding it.
+const JPEG_METADATA_BOX_TYPES: [[u8; 4]; 3] = [*b"Exif", *b"xml ", *b"jumb"];
+
+/// Options for one-call encoding functions, such as [`encode_rgb8`].
+#[derive(Debug, Clone)]
+pub struct EncodeOptions {
+    /// Encoding distance of lossy encoding, in range of `0.0 < distance <= 25.0`. Ignored if
+    /// `lossless` is set.
+    pub distance: f32,
+    pub lossless: bool,
+    pub effort: Effort,
+}
+
+impl Default for EncodeOptions {
+    fn default() -> Self {
+        Self {
+            distance: 1.0,
+            lossless: false,
+            effort: Effort::default(),
+        }
+    }
+}
+
+impl EncodeOptions {
+    pub(crate) fn is_lossless(&self) -> bool {
+        self.lossless
+    }
+
+    pub(crate) fn apply(&self, settings: &mut FrameSettings<'_>) -> Result<()> {
+        if self.is_lossless() {
+            settings.lossless(true)?;
+        } else {
+            settings.distance(self.distance)?;
+        }
+        settings.effort(self.effort);
+        Ok(())
+    }
+}
+
+/// Encodes 8-bit sRGB pixels as a single frame JPEG XL image.
+///
+/// `rgb` has 3 samples per pixel, in rows without padding. Returns
+/// [`Error::BufferSizeMismatch`] if its length doesn't match the dimension.
+pub fn encode_rgb8(
+    width: u32,
+    height: u32,
+    rgb: &[u8],
+    options: &EncodeOptions,
+) -> Result<Vec<u8>> {
+    let expected = width as usize * height as usize * 3;
+    if rgb.len() != expected {
+        return Err(Error::BufferSizeMismatch {
+            expected,
+            got: rgb.len(),
+        });
+    }
+
+    let mut encoder = JxlEncoder::new().ok_or(Error::OutOfMemory)?;
+
+    let mut basic_info = BasicInfo::new();
+    basic_info.xsize = width;
+    basic_info.ysize = height;
+    basic_info.bits_per_sample = 8;
+    basic_info.set_uses_original_profile(options.is_lossless());
+    encoder.set_basic_info(&basic_info)?;
+    encoder.set_color_encoding(&ColorEncoding::srgb(RenderingIntent::Relative))?;
+
+    let settings = encoder.create_frame_settings_with(|settings| options.apply(settings))?;
+    encoder
+        .add_frame(settings)?
+        .color_channels(3, SampleFormat::U8, rgb)?;
+    encoder.close_input();
+
+    let mut output = Vec::new();
+    for chunk in encoder.output_chunks() {
+        output.extend_from_slice(&chunk?);
+    }
+    Ok(output)
+}
+
+/// Re-encodes a JPEG XL image, copying its metadata boxes verbatim.
+///
+/// Boxes are read with [`JxlDecoder::raw_boxes`], so Brotli-compressed boxes are written back
+/// as stored. Images with JPEG reconstruction data are transcoded again from the reconstructed
+/// JPEG, in which case libjxl writes Exif, XMP and JUMBF boxes from the JPEG, and distance is
+/// ignored. Returns [`Error::NotSupported`] for animations, and for images with extra channels
+/// other than alpha.
+pub fn recompress(input: &[u8], options: &EncodeOptions) -> Result<Vec<u8>> {
+    let mut decoder = JxlDecoder::new().ok_or(Error::OutOfMemory)?;
+    let mut boxes = decoder.raw_boxes(input)?;
+    let mut encoder = JxlEncoder::new().ok_or(Error::OutOfMemory)?;
+
+    if decoder.has_jpeg_reconstruction(input)? {
+        let jpeg = decoder.decode_to_jpeg(input)?;
+        boxes.retain(|metadata_box| {
+            !JPEG_METADATA_BOX_TYPES.contains(&stored_box_type(metadata_box))
+        });
+        encoder.set_jpeg_reconstruction(true)?;
+        add_boxes(&mut encoder, &boxes)?;
+
+        let settings = encoder.create_frame_settings_with(|settings| {
+            settings.effort(options.effort);
+            Ok(())
+        })?;
+        encoder.add_frame(settings)?.jpeg(&jpeg)?;
+    } else {
+        let mut basic_info = decoder.basic_info(input)?;
+        let has_alpha = basic_info.alpha_bits > 0;
+        if basic_info.have_animation != 0 || basic_info.num_extra_channels > has_alpha as u32 {
+            return Err(Error::NotSupported);
+        }
+
+        let sample_format = if basic_info.exponent_bits_per_sample > 0 {
+            SampleFormat::F32
+        } else if basic_info.bits_per_sample <= 8 {
+            SampleFormat::U8
+        } else {
+            SampleFormat::U16
+        };
+        let num_channels = basic_info.num_color_channels + has_alpha as u32;
+        let pixels = decoder.decode_to_pixels(input, num_channels, sample_format)?;
+        // Pixels are decoded in the color space of the codestream, which is linear sRGB for XYB
+        // images with an ICC profile.
+        let icc = decoder.data_icc_profile(input)?;
+
+        // Previews are not carried over.
+        basic_info.have_preview = 0;
+        basic_info.set_uses_original_profile(options.is_lossless());
+        encoder.set_basic_info(&basic_info)?;
+        encoder.set_icc_profile(&icc)?;
+        add_boxes(&mut encoder, &boxes)?;
+
+        let settings = encoder.create_frame_settings_with(|settings| options.apply(settings))?;
+        encoder
+            .add_frame(settings)?
+            .color_channels(num_channels, sample_format, &pixels)?;
+    }
+    encoder.close_input();
+
+    let mut output = Vec::new();
+    for chunk in encoder.output_chunks() {
+        output.extend_from_slice(&chunk?);
+    }
+    Ok(output)
+}
+
+fn add_boxes(encoder: &mut JxlEncoder, boxes: &[MetadataBox]) -> Result<()> {
+    if boxes.is_empty() {
+        return Ok(());
+    }
+
+    encoder.use_boxes()?;
+    for metadata_box in boxes {
+        encoder.add_box(metadata_box)?;
+    }
+    encoder.close_boxes();
+    Ok(())
+}
+
+/// Type of the box, or of the compressed box if it's a raw `brob` box.
+fn stored_box_type(metadata_box: &MetadataBox) -> [u8; 4] {
+    let contents = metadata_box.contents();
+    if metadata_box.box_type() == *b"brob"
+        && let Some(box_type) = contents.get(..4)
+    {
+        box_type.try_into().unwrap()
+    } else {
+        metadata_box.box_type()
+    }
+}
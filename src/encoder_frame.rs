@@ -7,6 +7,10 @@ use crate::{Error, FrameSettingsKey, JxlEncoder, Result};
 pub struct EncoderFrame<'encoder> {
     encoder: &'encoder mut JxlEncoder,
     settings: Option<NonNull<sys::JxlEncoderFrameSettings>>,
+    /// Settings of the added pixel frame, used to set extra channel buffers.
+    pixel_frame_settings: Option<NonNull<sys::JxlEncoderFrameSettings>>,
+    near_lossless: u8,
+    lossless: bool,
 }
 
 impl<'encoder> EncoderFrame<'encoder> {
@@ -15,9 +19,15 @@ impl<'encoder> EncoderFrame<'encoder> {
         settings_key: FrameSettingsKey,
     ) -> Result<Self> {
         let settings = settings_key.try_index_raw(encoder)?;
+        let values = settings_key.try_index_values(encoder)?;
+        let near_lossless = values.near_lossless();
+        let lossless = values.lossless();
         Ok(Self {
             encoder,
             settings: Some(settings),
+            pixel_frame_settings: None,
+            near_lossless,
+            lossless,
         })
     }
 }
@@ -29,24 +39,30 @@ impl EncoderFrame<'_> {
         sample_format: SampleFormat,
         buffer: &[u8],
     ) -> Result<&mut Self> {
+        self.color_channels_with_format(&PixelFormat::new(num_channels, sample_format), buffer)
+    }
+
+    /// Adds a frame from a pixel buffer laid out as described by `pixel_format`.
+    ///
+    /// Returns [`Error::ApiUsage`] if the frame is lossless, but the basic info doesn't have
+    /// `uses_original_profile` enabled, as pixels would be converted to XYB lossily.
+    pub fn color_channels_with_format(
+        &mut self,
+        pixel_format: &PixelFormat,
+        buffer: &[u8],
+    ) -> Result<&mut Self> {
+        if self.lossless && !self.encoder.uses_original_profile {
+            return Err(Error::ApiUsage);
+        }
         let Some(settings) = self.settings.take() else {
             return Err(Error::ApiUsage);
         };
 
+        let quantized = quantize_near_lossless(buffer, pixel_format, self.near_lossless)?;
+        let buffer = quantized.as_deref().unwrap_or(buffer);
         let size = buffer.len();
         let buffer_ptr = buffer.as_ptr();
-
-        let pixel_format = sys::JxlPixelFormat {
-            num_channels,
-            data_type: match sample_format {
-                SampleFormat::U8 => sys::JxlDataType_JXL_TYPE_UINT8,
-                SampleFormat::U16 => sys::JxlDataType_JXL_TYPE_UINT16,
-                SampleFormat::F16 => sys::JxlDataType_JXL_TYPE_FLOAT16,
-                SampleFormat::F32 => sys::JxlDataType_JXL_TYPE_FLOAT,
-            },
-            endianness: sys::JxlEndianness_JXL_NATIVE_ENDIAN,
-            align: 0,
-        };
+        let pixel_format = pixel_format.to_sys();
 
         unsafe {
             let _ret = sys::JxlEncoderAddImageFrame(
@@ -57,10 +73,147 @@ impl EncoderFrame<'_> {
             );
             Error::try_from_libjxl_encoder(self.encoder.encoder)?;
         }
+        self.pixel_frame_settings = Some(settings);
+
+        Ok(self)
+    }
+
+    /// Sets samples of the extra channel at the given index, after adding color channels.
+    ///
+    /// `num_channels` of `pixel_format` is ignored, as extra channels always have a single
+    /// channel. Info of the extra channel should be set with
+    /// [`JxlEncoder::set_extra_channel_info`] beforehand. Every extra channel needs samples,
+    /// except for alpha which is already included in color channels.
+    pub fn extra_channel(
+        &mut self,
+        index: u32,
+        pixel_format: &PixelFormat,
+        buffer: &[u8],
+    ) -> Result<&mut Self> {
+        let Some(settings) = self.pixel_frame_settings else {
+            return Err(Error::ApiUsage);
+        };
+
+        let quantized = quantize_near_lossless(buffer, pixel_format, self.near_lossless)?;
+        let buffer = quantized.as_deref().unwrap_or(buffer);
+        let pixel_format = pixel_format.to_sys();
+        unsafe {
+            let _ret = sys::JxlEncoderSetExtraChannelBuffer(
+                settings.as_ptr(),
+                &pixel_format,
+                buffer.as_ptr().cast(),
+                buffer.len(),
+                index,
+            );
+            Error::try_from_libjxl_encoder(self.encoder.encoder)?;
+        }
 
         Ok(self)
     }
 
+    /// Adds a frame from interleaved CMYK samples, setting K to the black extra channel at the
+    /// given index.
+    ///
+    /// Samples are in the JPEG XL convention, where 0 means maximum ink. See
+    /// [`ExtraChannelType::Black`][crate::ExtraChannelType::Black] for setting up the encoder
+    /// for CMYK images.
+    pub fn cmyk_channels(
+        &mut self,
+        black_channel: u32,
+        sample_format: SampleFormat,
+        buffer: &[u8],
+    ) -> Result<&mut Self> {
+        let bytes_per_sample = sample_format.bytes_per_sample();
+        let pixels = buffer.chunks_exact(bytes_per_sample * 4);
+        if !pixels.remainder().is_empty() {
+            return Err(Error::ApiUsage);
+        }
+
+        let num_pixels = pixels.len();
+        let mut cmy = Vec::with_capacity(num_pixels * bytes_per_sample * 3);
+        let mut black = Vec::with_capacity(num_pixels * bytes_per_sample);
+        for pixel in pixels {
+            let (c, k) = pixel.split_at(bytes_per_sample * 3);
+            cmy.extend_from_slice(c);
+            black.extend_from_slice(k);
+        }
+
+        self.color_channels(3, sample_format, &cmy)?;
+        self.extra_channel(black_channel, &PixelFormat::new(1, sample_format), &black)
+    }
+
+    /// Adds a frame from planar samples, with a separate buffer for each channel.
+    ///
+    /// libjxl only accepts interleaved samples, so planes are interleaved into a temporary
+    /// buffer. Returns [`Error::ApiUsage`] if there are not 1 to 4 planes, and
+    /// [`Error::BufferSizeMismatch`] if planes have different lengths.
+    pub fn color_channels_planar(
+        &mut self,
+        sample_format: SampleFormat,
+        planes: &[&[u8]],
+    ) -> Result<&mut Self> {
+        let num_channels = planes.len();
+        let bytes_per_sample = sample_format.bytes_per_sample();
+        let Some(plane_len) = planes.first().map(|plane| plane.len()) else {
+            return Err(Error::ApiUsage);
+        };
+        if num_channels > 4 || plane_len % bytes_per_sample != 0 {
+            return Err(Error::ApiUsage);
+        }
+        if let Some(plane) = planes.iter().find(|plane| plane.len() != plane_len) {
+            return Err(Error::BufferSizeMismatch {
+                expected: plane_len,
+                got: plane.len(),
+            });
+        }
+
+        let pixel_stride = bytes_per_sample * num_channels;
+        let mut interleaved = vec![0u8; plane_len * num_channels];
+        for (channel, plane) in planes.iter().enumerate() {
+            let offset = channel * bytes_per_sample;
+            for (pixel, sample) in interleaved
+                .chunks_exact_mut(pixel_stride)
+                .zip(plane.chunks_exact(bytes_per_sample))
+            {
+                pixel[offset..offset + bytes_per_sample].copy_from_slice(sample);
+            }
+        }
+
+        self.color_channels(num_channels as u32, sample_format, &interleaved)
+    }
+
+    /// Adds a frame from typed planar samples, using the sample format of `T`.
+    ///
+    /// See [`color_channels_planar`][Self::color_channels_planar].
+    pub fn color_channels_planar_typed<T: Sample>(&mut self, planes: &[&[T]]) -> Result<&mut Self> {
+        let planes = planes
+            .iter()
+            .map(|plane| {
+                // SAFETY: Sample types are plain numeric types without padding bytes.
+                unsafe {
+                    std::slice::from_raw_parts(
+                        plane.as_ptr().cast::<u8>(),
+                        std::mem::size_of_val(*plane),
+                    )
+                }
+            })
+            .collect::<Vec<_>>();
+        self.color_channels_planar(T::SAMPLE_FORMAT, &planes)
+    }
+
+    /// Adds a frame from a typed pixel buffer, using the sample format of `T`.
+    pub fn color_channels_typed<T: Sample>(
+        &mut self,
+        num_channels: u32,
+        buffer: &[T],
+    ) -> Result<&mut Self> {
+        // SAFETY: Sample types are plain numeric types without padding bytes.
+        let bytes = unsafe {
+            std::slice::from_raw_parts(buffer.as_ptr().cast::<u8>(), std::mem::size_of_val(buffer))
+        };
+        self.color_channels(num_channels, T::SAMPLE_FORMAT, bytes)
+    }
+
     pub fn jpeg(&mut self, buffer: &[u8]) -> Result<&mut Self> {
         let Some(settings) = self.settings.take() else {
             return Err(Error::ApiUsage);
@@ -76,6 +229,62 @@ impl EncoderFrame<'_> {
 
         Ok(self)
     }
+
+    /// Adds a JPEG frame read from the reader.
+    ///
+    /// libjxl requires the whole JPEG bitstream at once, so the reader is read to the end into a
+    /// temporary buffer, which is dropped after libjxl takes its own copy. Errors from the
+    /// reader are returned as [`Error::Io`].
+    pub fn jpeg_from_reader(&mut self, mut reader: impl std::io::Read) -> Result<&mut Self> {
+        let mut buffer = Vec::new();
+        reader.read_to_end(&mut buffer)?;
+        self.jpeg(&buffer)
+    }
+}
+
+/// Quantizes integer samples to multiples of `2 * delta + 1`, so that each sample changes by at
+/// most `delta`. Returns `None` if `delta` is 0.
+fn quantize_near_lossless(
+    buffer: &[u8],
+    pixel_format: &PixelFormat,
+    delta: u8,
+) -> Result<Option<Vec<u8>>> {
+    if delta == 0 {
+        return Ok(None);
+    }
+
+    let step = 2 * delta as u32 + 1;
+    let quantize = |sample: u32, max: u32| ((sample + step / 2) / step * step).min(max);
+    let quantized = match pixel_format.sample_format {
+        SampleFormat::U8 => buffer
+            .iter()
+            .map(|&sample| quantize(sample as u32, u8::MAX as u32) as u8)
+            .collect(),
+        SampleFormat::U16 => {
+            let big_endian = match pixel_format.endianness {
+                Endianness::Native => cfg!(target_endian = "big"),
+                Endianness::Little => false,
+                Endianness::Big => true,
+            };
+            let samples = buffer.chunks_exact(2);
+            let remainder = samples.remainder();
+            let mut quantized = Vec::with_capacity(buffer.len());
+            for sample in samples {
+                let sample = [sample[0], sample[1]];
+                if big_endian {
+                    let sample = quantize(u16::from_be_bytes(sample) as u32, u16::MAX as u32);
+                    quantized.extend((sample as u16).to_be_bytes());
+                } else {
+                    let sample = quantize(u16::from_le_bytes(sample) as u32, u16::MAX as u32);
+                    quantized.extend((sample as u16).to_le_bytes());
+                }
+            }
+            quantized.extend_from_slice(remainder);
+            quantized
+        }
+        SampleFormat::F16 | SampleFormat::F32 => return Err(Error::ApiUsage),
+    };
+    Ok(Some(quantized))
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
@@ -85,3 +294,169 @@ pub enum SampleFormat {
     F16,
     F32,
 }
+
+impl SampleFormat {
+    #[inline]
+    pub fn bytes_per_sample(self) -> usize {
+        match self {
+            Self::U8 => 1,
+            Self::U16 | Self::F16 => 2,
+            Self::F32 => 4,
+        }
+    }
+
+    /// Bit depth and exponent bits of floating point formats.
+    #[inline]
+    pub fn float_depth(self) -> Option<(u32, u32)> {
+        match self {
+            Self::U8 | Self::U16 => None,
+            Self::F16 => Some((16, 5)),
+            Self::F32 => Some((32, 8)),
+        }
+    }
+}
+
+/// Range of integer samples in pixel buffers.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub enum BitDepth {
+    /// Samples use the full range of the sample format, e.g. `0..=65535` for
+    /// [`SampleFormat::U16`].
+    #[default]
+    FromPixelFormat,
+    /// Samples use the range of the bit depth of the image, e.g. `0..=1023` for 10-bit images.
+    FromCodestream,
+    /// Samples use the range of the given bit depth. Only supported by the decoder.
+    Custom {
+        bits_per_sample: u32,
+        exponent_bits_per_sample: u32,
+    },
+}
+
+impl From<BitDepth> for sys::JxlBitDepth {
+    fn from(value: BitDepth) -> Self {
+        let (type_, bits_per_sample, exponent_bits_per_sample) = match value {
+            BitDepth::FromPixelFormat => {
+                (sys::JxlBitDepthType_JXL_BIT_DEPTH_FROM_PIXEL_FORMAT, 0, 0)
+            }
+            BitDepth::FromCodestream => (sys::JxlBitDepthType_JXL_BIT_DEPTH_FROM_CODESTREAM, 0, 0),
+            BitDepth::Custom {
+                bits_per_sample,
+                exponent_bits_per_sample,
+            } => (
+                sys::JxlBitDepthType_JXL_BIT_DEPTH_CUSTOM,
+                bits_per_sample,
+                exponent_bits_per_sample,
+            ),
+        };
+        Self {
+            type_,
+            bits_per_sample,
+            exponent_bits_per_sample,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub enum Endianness {
+    #[default]
+    Native,
+    Little,
+    Big,
+}
+
+impl From<Endianness> for sys::JxlEndianness {
+    fn from(value: Endianness) -> Self {
+        match value {
+            Endianness::Native => sys::JxlEndianness_JXL_NATIVE_ENDIAN,
+            Endianness::Little => sys::JxlEndianness_JXL_LITTLE_ENDIAN,
+            Endianness::Big => sys::JxlEndianness_JXL_BIG_ENDIAN,
+        }
+    }
+}
+
+/// Layout of interleaved pixel buffers, used both for encoding and decoding.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct PixelFormat {
+    pub num_channels: u32,
+    pub sample_format: SampleFormat,
+    pub endianness: Endianness,
+    /// Alignment of each row in bytes, or 0 to pack rows tightly.
+    pub align: usize,
+}
+
+impl PixelFormat {
+    /// Creates a tightly packed pixel format in native endianness.
+    pub fn new(num_channels: u32, sample_format: SampleFormat) -> Self {
+        Self {
+            num_channels,
+            sample_format,
+            endianness: Endianness::Native,
+            align: 0,
+        }
+    }
+
+    pub fn set_endianness(&mut self, endianness: Endianness) -> &mut Self {
+        self.endianness = endianness;
+        self
+    }
+
+    pub fn set_align(&mut self, align: usize) -> &mut Self {
+        self.align = align;
+        self
+    }
+
+    /// Length of a row of `xsize` pixels in bytes, including alignment padding.
+    pub fn row_stride(&self, xsize: u32) -> usize {
+        let stride =
+            xsize as usize * self.num_channels as usize * self.sample_format.bytes_per_sample();
+        if self.align > 1 {
+            stride.next_multiple_of(self.align)
+        } else {
+            stride
+        }
+    }
+
+    pub fn to_sys(&self) -> sys::JxlPixelFormat {
+        sys::JxlPixelFormat {
+            num_channels: self.num_channels,
+            data_type: match self.sample_format {
+                SampleFormat::U8 => sys::JxlDataType_JXL_TYPE_UINT8,
+                SampleFormat::U16 => sys::JxlDataType_JXL_TYPE_UINT16,
+                SampleFormat::F16 => sys::JxlDataType_JXL_TYPE_FLOAT16,
+                SampleFormat::F32 => sys::JxlDataType_JXL_TYPE_FLOAT,
+            },
+            endianness: self.endianness.into(),
+            align: self.align,
+        }
+    }
+}
+
+/// Sample types of pixel buffers, in native endianness.
+pub trait Sample: sealed::Sealed + Copy {
+    const SAMPLE_FORMAT: SampleFormat;
+}
+
+impl Sample for u8 {
+    const SAMPLE_FORMAT: SampleFormat = SampleFormat::U8;
+}
+
+impl Sample for u16 {
+    const SAMPLE_FORMAT: SampleFormat = SampleFormat::U16;
+}
+
+impl Sample for half::f16 {
+    const SAMPLE_FORMAT: SampleFormat = SampleFormat::F16;
+}
+
+impl Sample for f32 {
+    const SAMPLE_FORMAT: SampleFormat = SampleFormat::F32;
+}
+
+mod sealed {
+    pub trait Sealed {}
+
+    impl Sealed for u8 {}
+    impl Sealed for u16 {}
+    impl Sealed for half::f16 {}
+    impl Sealed for f32 {}
+}
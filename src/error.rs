@@ -14,6 +14,23 @@ pub enum Error {
     BadInput,
     #[error("not supported")]
     NotSupported,
+    #[error("image doesn't have JPEG reconstruction data")]
+    NotJpegReconstructible,
+    #[error("input is truncated")]
+    TruncatedInput,
+    #[error("failed to read input")]
+    Io(#[from] std::io::Error),
+    #[error("encoding cancelled")]
+    Cancelled,
+    /// Encoding panicked, e.g. in a decoder of the `image` crate on malformed input.
+    #[error("panicked while encoding")]
+    Panicked,
+    /// Pixel buffer doesn't have the size expected from the image dimensions and pixel format.
+    #[error("buffer size mismatch: expected {expected} bytes, got {got}")]
+    BufferSizeMismatch { expected: usize, got: usize },
+    /// Unexpected status or error code returned by libjxl.
+    #[error("unexpected libjxl status {0}")]
+    Libjxl(i32),
     #[error("unknown error")]
     Unknown,
 }
@@ -31,7 +48,7 @@ impl Error {
                 sys::JxlEncoderError_JXL_ENC_ERR_API_USAGE => Self::ApiUsage,
                 sys::JxlEncoderError_JXL_ENC_ERR_BAD_INPUT => Self::BadInput,
                 sys::JxlEncoderError_JXL_ENC_ERR_NOT_SUPPORTED => Self::NotSupported,
-                _ => Self::Unknown,
+                error => Self::Libjxl(error as i32),
             })
         }
     }
@@ -39,8 +56,7 @@ impl Error {
     pub(crate) fn try_from_libjxl_decoder(ret: sys::JxlDecoderStatus) -> Result<(), Self> {
         Err(match ret {
             sys::JxlDecoderStatus_JXL_DEC_SUCCESS => return Ok(()),
-            // TODO
-            _ => Self::Unknown,
+            ret => Self::Libjxl(ret as i32),
         })
     }
 }
@@ -0,0 +1,113 @@
+use std::mem::MaybeUninit;
+use std::ops::{Deref, DerefMut};
+
+use crate::sys;
+
+pub use sys::JxlExtraChannelInfo as ExtraChannelInfoData;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ExtraChannelType {
+    Alpha,
+    Depth,
+    SpotColor,
+    SelectionMask,
+    /// Black (K) channel of CMYK images.
+    ///
+    /// CMYK images store CMY in the color channels and K in this extra channel. Color encoding
+    /// should be set with a CMYK ICC profile, and the original profile should be used as XYB
+    /// cannot represent CMYK. Samples of 0 mean maximum ink, and 1 mean no ink. CMYK requires
+    /// codestream level 10.
+    Black,
+    Cfa,
+    Thermal,
+    Optional,
+    Unknown,
+}
+
+impl ExtraChannelType {
+    pub fn from_sys(value: sys::JxlExtraChannelType) -> Self {
+        match value {
+            sys::JxlExtraChannelType_JXL_CHANNEL_ALPHA => Self::Alpha,
+            sys::JxlExtraChannelType_JXL_CHANNEL_DEPTH => Self::Depth,
+            sys::JxlExtraChannelType_JXL_CHANNEL_SPOT_COLOR => Self::SpotColor,
+            sys::JxlExtraChannelType_JXL_CHANNEL_SELECTION_MASK => Self::SelectionMask,
+            sys::JxlExtraChannelType_JXL_CHANNEL_BLACK => Self::Black,
+            sys::JxlExtraChannelType_JXL_CHANNEL_CFA => Self::Cfa,
+            sys::JxlExtraChannelType_JXL_CHANNEL_THERMAL => Self::Thermal,
+            sys::JxlExtraChannelType_JXL_CHANNEL_OPTIONAL => Self::Optional,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+impl From<ExtraChannelType> for sys::JxlExtraChannelType {
+    fn from(value: ExtraChannelType) -> Self {
+        match value {
+            ExtraChannelType::Alpha => sys::JxlExtraChannelType_JXL_CHANNEL_ALPHA,
+            ExtraChannelType::Depth => sys::JxlExtraChannelType_JXL_CHANNEL_DEPTH,
+            ExtraChannelType::SpotColor => sys::JxlExtraChannelType_JXL_CHANNEL_SPOT_COLOR,
+            ExtraChannelType::SelectionMask => sys::JxlExtraChannelType_JXL_CHANNEL_SELECTION_MASK,
+            ExtraChannelType::Black => sys::JxlExtraChannelType_JXL_CHANNEL_BLACK,
+            ExtraChannelType::Cfa => sys::JxlExtraChannelType_JXL_CHANNEL_CFA,
+            ExtraChannelType::Thermal => sys::JxlExtraChannelType_JXL_CHANNEL_THERMAL,
+            ExtraChannelType::Optional => sys::JxlExtraChannelType_JXL_CHANNEL_OPTIONAL,
+            ExtraChannelType::Unknown => sys::JxlExtraChannelType_JXL_CHANNEL_UNKNOWN,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ExtraChannelInfo(pub(crate) ExtraChannelInfoData);
+
+impl Deref for ExtraChannelInfo {
+    type Target = ExtraChannelInfoData;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for ExtraChannelInfo {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl ExtraChannelInfo {
+    /// Creates info of an 8-bit extra channel of the given type.
+    pub fn new(channel_type: ExtraChannelType) -> Self {
+        let mut info = MaybeUninit::uninit();
+        unsafe {
+            sys::JxlEncoderInitExtraChannelInfo(channel_type.into(), info.as_mut_ptr());
+            Self(info.assume_init())
+        }
+    }
+
+    /// Creates info of an 8-bit spot color channel, with the color in linear RGBA.
+    ///
+    /// The alpha component is the opacity of the spot color at the maximum sample value. The
+    /// name of the spot color, such as a Pantone name, can be set with
+    /// [`JxlEncoder::set_extra_channel_name`][crate::JxlEncoder::set_extra_channel_name].
+    pub fn spot_color(color: [f32; 4]) -> Self {
+        let mut info = Self::new(ExtraChannelType::SpotColor);
+        info.0.spot_color = color;
+        info
+    }
+
+    #[inline]
+    pub fn channel_type(&self) -> ExtraChannelType {
+        ExtraChannelType::from_sys(self.0.type_)
+    }
+
+    /// Color of the spot color channel in linear RGBA, if the channel is a spot color channel.
+    pub fn spot_color_value(&self) -> Option<[f32; 4]> {
+        (self.channel_type() == ExtraChannelType::SpotColor).then_some(self.0.spot_color)
+    }
+
+    /// Sets bit depth of integer samples.
+    pub fn set_bits_per_sample(&mut self, bits_per_sample: u32) -> &mut Self {
+        self.0.bits_per_sample = bits_per_sample;
+        self.0.exponent_bits_per_sample = 0;
+        self
+    }
+}
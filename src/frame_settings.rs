@@ -1,3 +1,4 @@
+use std::collections::BTreeMap;
 use std::mem::MaybeUninit;
 use std::ops::{Deref, DerefMut};
 use std::ptr::NonNull;
@@ -8,24 +9,38 @@ use super::{Error, JxlEncoder, Result};
 pub use sys::JxlFrameHeader as FrameHeaderData;
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
-pub struct FrameSettingsKey(NonNull<sys::JxlEncoder>, usize);
+pub struct FrameSettingsKey(NonNull<sys::JxlEncoder>, u64, usize);
 
 impl FrameSettingsKey {
+    /// Whether the key is for the encoder, and is created after the last reset of it.
     #[inline]
     pub fn is_for_encoder(self, encoder: &JxlEncoder) -> bool {
-        self.0 == encoder.encoder
+        self.0 == encoder.encoder && self.1 == encoder.generation
     }
 
-    pub(crate) fn try_index(self, encoder: &mut JxlEncoder) -> Result<FrameSettings> {
+    pub(crate) fn try_index(self, encoder: &mut JxlEncoder) -> Result<FrameSettings<'_>> {
         if !self.is_for_encoder(encoder) {
             return Err(Error::Unknown);
         }
 
-        let settings =
-            unsafe { FrameSettings::from_raw(encoder.encoder, encoder.frame_settings[self.1]) };
+        let settings = unsafe {
+            FrameSettings::from_raw(
+                encoder.encoder,
+                encoder.frame_settings[self.2],
+                &mut encoder.frame_settings_values[self.2],
+            )
+        };
         Ok(settings)
     }
 
+    pub(crate) fn try_index_values(self, encoder: &JxlEncoder) -> Result<&FrameSettingsValues> {
+        if !self.is_for_encoder(encoder) {
+            return Err(Error::Unknown);
+        }
+
+        Ok(&encoder.frame_settings_values[self.2])
+    }
+
     pub(crate) fn try_index_raw(
         self,
         encoder: &mut JxlEncoder,
@@ -34,14 +49,14 @@ impl FrameSettingsKey {
             return Err(Error::Unknown);
         }
 
-        Ok(encoder.frame_settings[self.1])
+        Ok(encoder.frame_settings[self.2])
     }
 }
 
 pub struct FrameSettings<'encoder> {
     encoder: NonNull<sys::JxlEncoder>,
     settings: NonNull<sys::JxlEncoderFrameSettings>,
-    _phantom: std::marker::PhantomData<&'encoder mut ()>,
+    values: &'encoder mut FrameSettingsValues,
 }
 
 impl<'encoder> FrameSettings<'encoder> {
@@ -49,14 +64,21 @@ impl<'encoder> FrameSettings<'encoder> {
         encoder: &'encoder mut JxlEncoder,
         source: Option<FrameSettingsKey>,
     ) -> Result<(Self, FrameSettingsKey)> {
-        let next_key = FrameSettingsKey(encoder.encoder, encoder.frame_settings.len());
-        let source_ptr = if let Some(FrameSettingsKey(base_encoder, idx)) = source {
-            if base_encoder != encoder.encoder {
+        let next_key = FrameSettingsKey(
+            encoder.encoder,
+            encoder.generation,
+            encoder.frame_settings.len(),
+        );
+        let (source_ptr, values) = if let Some(source) = source {
+            if !source.is_for_encoder(encoder) {
                 return Err(Error::Unknown);
             }
-            encoder.frame_settings[idx].as_ptr()
+            (
+                encoder.frame_settings[source.2].as_ptr(),
+                encoder.frame_settings_values[source.2].clone(),
+            )
         } else {
-            std::ptr::null_mut()
+            (std::ptr::null_mut(), FrameSettingsValues::default())
         };
 
         let settings = unsafe {
@@ -65,10 +87,11 @@ impl<'encoder> FrameSettings<'encoder> {
         };
 
         encoder.frame_settings.push(settings);
+        encoder.frame_settings_values.push(values);
         let this = Self {
             encoder: encoder.encoder,
             settings,
-            _phantom: Default::default(),
+            values: encoder.frame_settings_values.last_mut().unwrap(),
         };
         Ok((this, next_key))
     }
@@ -77,11 +100,12 @@ impl<'encoder> FrameSettings<'encoder> {
     unsafe fn from_raw(
         encoder: NonNull<sys::JxlEncoder>,
         settings: NonNull<sys::JxlEncoderFrameSettings>,
+        values: &'encoder mut FrameSettingsValues,
     ) -> Self {
         Self {
             encoder,
             settings,
-            _phantom: Default::default(),
+            values,
         }
     }
 }
@@ -91,18 +115,27 @@ impl FrameSettings<'_> {
     fn set_raw_i64(&mut self, option: sys::JxlEncoderFrameSettingId, value: i64) -> Result<()> {
         unsafe {
             let _ret = sys::JxlEncoderFrameSettingsSetOption(self.settings.as_ptr(), option, value);
-            Error::try_from_libjxl_encoder(self.encoder)
+            Error::try_from_libjxl_encoder(self.encoder)?;
         }
+        self.values.options.insert(option, value);
+        Ok(())
     }
 
     #[inline]
-    #[expect(unused)]
     fn set_raw_f32(&mut self, option: sys::JxlEncoderFrameSettingId, value: f32) -> Result<()> {
         unsafe {
             let _ret =
                 sys::JxlEncoderFrameSettingsSetFloatOption(self.settings.as_ptr(), option, value);
-            Error::try_from_libjxl_encoder(self.encoder)
+            Error::try_from_libjxl_encoder(self.encoder)?;
         }
+        self.values.float_options.insert(option, value);
+        Ok(())
+    }
+
+    /// Values set to the frame settings so far.
+    #[inline]
+    pub fn values(&self) -> &FrameSettingsValues {
+        self.values
     }
 
     pub fn frame_header(&mut self, frame_header: &FrameHeader) -> Result<&mut Self> {
@@ -122,17 +155,90 @@ impl FrameSettings<'_> {
         self
     }
 
-    /// Setting distance smaller than 0.01 will trigger lossless encoding.
+    /// Sets distance of lossy encoding. Returns [`Error::ApiUsage`] if the distance is out of
+    /// range of `0.0 < distance <= 25.0`, or if lossless encoding is enabled with
+    /// [`lossless`][Self::lossless].
+    ///
+    /// The distance applies to all color channels. libjxl doesn't expose per-channel distance
+    /// of XYB channels, so chroma can't be quantized separately from luma; it already spends
+    /// fewer bits on X and B channels based on its perceptual model. Near-lossless mode is
+    /// disabled.
     pub fn distance(&mut self, distance: f32) -> Result<&mut Self> {
+        if !(distance > 0. && distance <= 25.) || self.values.lossless {
+            return Err(Error::ApiUsage);
+        }
+
         unsafe {
-            if distance < 0.01 {
-                sys::JxlEncoderSetFrameLossless(self.settings.as_ptr(), sys::JXL_TRUE as i32);
-            } else {
-                sys::JxlEncoderSetFrameDistance(self.settings.as_ptr(), distance);
-            }
+            sys::JxlEncoderSetFrameDistance(self.settings.as_ptr(), distance);
+            Error::try_from_libjxl_encoder(self.encoder)?;
+        }
+        self.values.distance = Some(distance);
+        self.values.near_lossless = 0;
+
+        Ok(self)
+    }
+
+    /// Sets whether the frame is encoded losslessly.
+    ///
+    /// Lossless encoding takes precedence over the distance, so the distance set before is
+    /// cleared. Lossless encoding should be disabled before setting a distance again, which
+    /// also disables near-lossless mode.
+    pub fn lossless(&mut self, lossless: bool) -> Result<&mut Self> {
+        unsafe {
+            sys::JxlEncoderSetFrameLossless(self.settings.as_ptr(), lossless as i32);
+            Error::try_from_libjxl_encoder(self.encoder)?;
+        }
+        self.values.lossless = lossless;
+        if lossless {
+            self.values.distance = None;
+        } else {
+            self.values.near_lossless = 0;
+        }
+
+        Ok(self)
+    }
+
+    /// Sets distance of the extra channel at the given index. Distance of 0 encodes the channel
+    /// losslessly, and `None` makes the channel use the frame distance, which is the default.
+    ///
+    /// Returns [`Error::ApiUsage`] if the distance is not in the range `0..=25`.
+    pub fn extra_channel_distance(
+        &mut self,
+        index: u32,
+        distance: Option<f32>,
+    ) -> Result<&mut Self> {
+        if let Some(distance) = distance
+            && !(0.0..=25.0).contains(&distance)
+        {
+            return Err(Error::ApiUsage);
+        }
+
+        unsafe {
+            // -1 makes libjxl derive the distance from the frame distance.
+            sys::JxlEncoderSetExtraChannelDistance(
+                self.settings.as_ptr(),
+                index as usize,
+                distance.unwrap_or(-1.),
+            );
             Error::try_from_libjxl_encoder(self.encoder)?;
         }
+        match distance {
+            Some(distance) => self.values.extra_channel_distances.insert(index, distance),
+            None => self.values.extra_channel_distances.remove(&index),
+        };
+
+        Ok(self)
+    }
 
+    /// Encodes pixels with error of at most `delta` per sample, or losslessly if `delta` is 0.
+    ///
+    /// libjxl doesn't have a near-lossless mode, so integer samples are quantized to multiples
+    /// of `2 * delta + 1` before being encoded losslessly in Modular mode, which lets libjxl use
+    /// channel palettes. Adding floating point samples with near-lossless mode enabled returns
+    /// [`Error::ApiUsage`].
+    pub fn near_lossless(&mut self, delta: u8) -> Result<&mut Self> {
+        self.lossless(true)?.modular(Some(true));
+        self.values.near_lossless = delta;
         Ok(self)
     }
 
@@ -185,6 +291,10 @@ impl FrameSettings<'_> {
     }
 
     pub fn modular(&mut self, modular: Option<bool>) -> &mut Self {
+        // Near-lossless mode requires Modular mode.
+        if modular == Some(false) {
+            self.values.near_lossless = 0;
+        }
         let modular = modular.map(|x| x as i64).unwrap_or(-1);
         self.set_raw_i64(
             sys::JxlEncoderFrameSettingId_JXL_ENC_FRAME_SETTING_MODULAR,
@@ -194,17 +304,349 @@ impl FrameSettings<'_> {
         self
     }
 
+    /// Percentage of pixels used to learn MA trees in Modular mode.
+    ///
+    /// Value of 0 disables MA tree learning, and values above 100 are also permitted.
+    pub fn modular_ma_tree_learning_percent(&mut self, percent: f32) -> Result<&mut Self> {
+        if percent.is_nan() || percent < 0.0 {
+            return Err(Error::ApiUsage);
+        }
+
+        self.set_raw_f32(
+            sys::JxlEncoderFrameSettingId_JXL_ENC_FRAME_SETTING_MODULAR_MA_TREE_LEARNING_PERCENT,
+            percent,
+        )?;
+        Ok(self)
+    }
+
+    /// Number of previous channels referenced by MA tree properties in Modular mode, in range of
+    /// `0..=11`.
+    pub fn modular_nb_prev_channels(&mut self, n: Option<u32>) -> Result<&mut Self> {
+        let n = if let Some(n) = n {
+            if !(0..=11).contains(&n) {
+                return Err(Error::ApiUsage);
+            }
+            n as i64
+        } else {
+            -1i64
+        };
+
+        self.set_raw_i64(
+            sys::JxlEncoderFrameSettingId_JXL_ENC_FRAME_SETTING_MODULAR_NB_PREV_CHANNELS,
+            n,
+        )?;
+        Ok(self)
+    }
+
+    /// Maximum number of colors to use color palette in Modular mode.
+    ///
+    /// Negative value disables color palette.
+    pub fn palette_colors(&mut self, n: Option<i32>) -> Result<&mut Self> {
+        let n = n.map(|n| n.max(0) as i64).unwrap_or(-1);
+        self.set_raw_i64(
+            sys::JxlEncoderFrameSettingId_JXL_ENC_FRAME_SETTING_PALETTE_COLORS,
+            n,
+        )?;
+        Ok(self)
+    }
+
+    /// Enables lossy delta palette in Modular mode.
+    ///
+    /// Lossy palette is a lossy use of delta palette, so it only works for Modular frames. It
+    /// typically needs the internal color transform to be none (no XYB), and is often combined
+    /// with [`palette_colors`][Self::palette_colors] of zero.
+    pub fn lossy_palette(&mut self, lossy_palette: Option<bool>) -> &mut Self {
+        let lossy_palette = lossy_palette.map(|x| x as i64).unwrap_or(-1);
+        self.set_raw_i64(
+            sys::JxlEncoderFrameSettingId_JXL_ENC_FRAME_SETTING_LOSSY_PALETTE,
+            lossy_palette,
+        )
+        .unwrap();
+        self
+    }
+
+    /// Disables perceptual optimizations, which speeds up lossless and Modular encoding.
+    pub fn disable_perceptual_heuristics(&mut self, disable: Option<bool>) -> &mut Self {
+        let disable = disable.map(|x| x as i64).unwrap_or(-1);
+        self.set_raw_i64(
+            sys::JxlEncoderFrameSettingId_JXL_ENC_FRAME_SETTING_DISABLE_PERCEPTUAL_HEURISTICS,
+            disable,
+        )
+        .unwrap();
+        self
+    }
+
+    /// Speed tier when decoding output image, in range of `0..=4`.
     pub fn decoding_speed(&mut self, speed: u32) -> Result<&mut Self> {
+        if !(0..=4).contains(&speed) {
+            return Err(Error::ApiUsage);
+        }
+
         self.set_raw_i64(
             sys::JxlEncoderFrameSettingId_JXL_ENC_FRAME_SETTING_DECODING_SPEED,
             speed as i64,
         )?;
         Ok(self)
     }
+
+    /// Downsampling factor applied before compression, one of 1, 2, 4 or 8.
+    ///
+    /// The image is upsampled back to the original size in the decoder. This applies to all
+    /// color channels; libjxl doesn't support chroma subsampling of pixel input, which is only
+    /// preserved when recompressing JPEG frames.
+    pub fn resampling(&mut self, factor: Option<u32>) -> Result<&mut Self> {
+        let factor = if let Some(factor) = factor {
+            if ![1, 2, 4, 8].contains(&factor) {
+                return Err(Error::ApiUsage);
+            }
+            factor as i64
+        } else {
+            -1i64
+        };
+
+        self.set_raw_i64(
+            sys::JxlEncoderFrameSettingId_JXL_ENC_FRAME_SETTING_RESAMPLING,
+            factor,
+        )?;
+        Ok(self)
+    }
+
+    /// Downsampling factor applied to extra channels before compression, one of 1, 2, 4 or 8.
+    ///
+    /// This is independent from color channel [`resampling`][Self::resampling].
+    pub fn extra_channel_resampling(&mut self, factor: u32) -> Result<&mut Self> {
+        if ![1, 2, 4, 8].contains(&factor) {
+            return Err(Error::ApiUsage);
+        }
+
+        self.set_raw_i64(
+            sys::JxlEncoderFrameSettingId_JXL_ENC_FRAME_SETTING_EXTRA_CHANNEL_RESAMPLING,
+            factor as i64,
+        )?;
+        Ok(self)
+    }
+
+    /// Indicates that the input frame is already downsampled by the [`resampling`] factor.
+    ///
+    /// The frame must then be given in the downsampled resolution, while the basic info keeps
+    /// the original dimension which the decoder upsamples the frame to.
+    ///
+    /// [`resampling`]: Self::resampling
+    pub fn already_downsampled(&mut self, already_downsampled: Option<bool>) -> &mut Self {
+        let already_downsampled = already_downsampled.map(|x| x as i64).unwrap_or(-1);
+        self.set_raw_i64(
+            sys::JxlEncoderFrameSettingId_JXL_ENC_FRAME_SETTING_ALREADY_DOWNSAMPLED,
+            already_downsampled,
+        )
+        .unwrap();
+        self
+    }
+
+    pub fn group_order(&mut self, group_order: GroupOrder) -> &mut Self {
+        self.set_raw_i64(
+            sys::JxlEncoderFrameSettingId_JXL_ENC_FRAME_SETTING_GROUP_ORDER,
+            group_order as i64,
+        )
+        .unwrap();
+        self
+    }
+
+    /// Sets the center of [`GroupOrder::CenterFirst`] group order, in pixels.
+    ///
+    /// The center defaults to the middle of the image.
+    pub fn group_order_center(&mut self, x: u32, y: u32) -> &mut Self {
+        self.set_raw_i64(
+            sys::JxlEncoderFrameSettingId_JXL_ENC_FRAME_SETTING_GROUP_ORDER_CENTER_X,
+            x as i64,
+        )
+        .unwrap();
+        self.set_raw_i64(
+            sys::JxlEncoderFrameSettingId_JXL_ENC_FRAME_SETTING_GROUP_ORDER_CENTER_Y,
+            y as i64,
+        )
+        .unwrap();
+        self
+    }
+
+    /// Buffering strategy of chunked frames, in range of `0..=3`.
+    ///
+    /// Value of 0 buffers everything, and higher values use streaming input and output for
+    /// smaller images, which reduces memory usage and latency at the cost of compression density.
+    pub fn buffering(&mut self, level: Option<u32>) -> Result<&mut Self> {
+        let level = if let Some(level) = level {
+            if !(0..=3).contains(&level) {
+                return Err(Error::ApiUsage);
+            }
+            level as i64
+        } else {
+            -1i64
+        };
+
+        self.set_raw_i64(
+            sys::JxlEncoderFrameSettingId_JXL_ENC_FRAME_SETTING_BUFFERING,
+            level,
+        )?;
+        Ok(self)
+    }
+
+    /// Indexes frames added with these settings in the frame index (`jxli`) box, which lets
+    /// decoders seek to the frames without decoding preceding ones.
+    ///
+    /// If any frames are indexed, the first frame must also be indexed. Only keyframes, which
+    /// don't use cropping, blending or patches, can be indexed; adding other frames fails.
+    pub fn frame_index_box(&mut self, index: bool) -> Result<&mut Self> {
+        self.set_raw_i64(
+            sys::JxlEncoderFrameSettingId_JXL_ENC_FRAME_INDEX_BOX,
+            index as i64,
+        )?;
+        Ok(self)
+    }
+
+    /// Keeps or discards Exif metadata of a JPEG frame.
+    ///
+    /// Exif metadata cannot be discarded if JPEG reconstruction is enabled.
+    pub fn jpeg_keep_exif(&mut self, keep: bool) -> Result<&mut Self> {
+        self.set_raw_i64(
+            sys::JxlEncoderFrameSettingId_JXL_ENC_FRAME_SETTING_JPEG_KEEP_EXIF,
+            keep as i64,
+        )?;
+        Ok(self)
+    }
+
+    /// Keeps or discards XMP metadata of a JPEG frame.
+    ///
+    /// XMP metadata cannot be discarded if JPEG reconstruction is enabled.
+    pub fn jpeg_keep_xmp(&mut self, keep: bool) -> Result<&mut Self> {
+        self.set_raw_i64(
+            sys::JxlEncoderFrameSettingId_JXL_ENC_FRAME_SETTING_JPEG_KEEP_XMP,
+            keep as i64,
+        )?;
+        Ok(self)
+    }
+
+    /// Keeps or discards JUMBF metadata of a JPEG frame.
+    pub fn jpeg_keep_jumbf(&mut self, keep: bool) -> Result<&mut Self> {
+        self.set_raw_i64(
+            sys::JxlEncoderFrameSettingId_JXL_ENC_FRAME_SETTING_JPEG_KEEP_JUMBF,
+            keep as i64,
+        )?;
+        Ok(self)
+    }
+
+    /// Enables or disables Brotli compression of metadata boxes derived from a JPEG frame.
+    ///
+    /// This has no effect on boxes added with [`JxlEncoder::add_box`].
+    pub fn jpeg_compress_boxes(&mut self, compress: Option<bool>) -> &mut Self {
+        let compress = compress.map(|x| x as i64).unwrap_or(-1);
+        self.set_raw_i64(
+            sys::JxlEncoderFrameSettingId_JXL_ENC_FRAME_SETTING_JPEG_COMPRESS_BOXES,
+            compress,
+        )
+        .unwrap();
+        self
+    }
+
+    /// Brotli effort used in JPEG recompression and compressed metadata boxes, in range of
+    /// `0..=11`.
+    pub fn brotli_effort(&mut self, effort: Option<u32>) -> Result<&mut Self> {
+        let effort = if let Some(effort) = effort {
+            if !(0..=11).contains(&effort) {
+                return Err(Error::ApiUsage);
+            }
+            effort as i64
+        } else {
+            -1i64
+        };
+
+        self.set_raw_i64(
+            sys::JxlEncoderFrameSettingId_JXL_ENC_FRAME_SETTING_BROTLI_EFFORT,
+            effort,
+        )?;
+        Ok(self)
+    }
+
+    /// Tunes settings in favor of smaller output at the cost of encoding speed.
+    ///
+    /// This uses all pixels for MA tree learning, the largest Modular group size and the maximum
+    /// Brotli effort, and disables faster decoding. Effort and distance are left untouched.
+    pub fn optimize_for_size(&mut self) -> Result<&mut Self> {
+        self.modular_ma_tree_learning_percent(100.0)?
+            .decoding_speed(0)?
+            .brotli_effort(Some(11))?
+            .set_raw_i64(
+                sys::JxlEncoderFrameSettingId_JXL_ENC_FRAME_SETTING_MODULAR_GROUP_SIZE,
+                3,
+            )?;
+        Ok(self)
+    }
+}
+
+/// Values set to frame settings.
+///
+/// libjxl doesn't provide a way to query frame settings, so values are recorded as they're set
+/// through [`FrameSettings`]. Values not set yet use libjxl defaults.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FrameSettingsValues {
+    options: BTreeMap<sys::JxlEncoderFrameSettingId, i64>,
+    float_options: BTreeMap<sys::JxlEncoderFrameSettingId, f32>,
+    distance: Option<f32>,
+    lossless: bool,
+    extra_channel_distances: BTreeMap<u32, f32>,
+    near_lossless: u8,
+}
+
+impl FrameSettingsValues {
+    /// Value of an integer option, where -1 means the libjxl default.
+    #[inline]
+    pub fn option(&self, option: sys::JxlEncoderFrameSettingId) -> Option<i64> {
+        self.options.get(&option).copied()
+    }
+
+    #[inline]
+    pub fn float_option(&self, option: sys::JxlEncoderFrameSettingId) -> Option<f32> {
+        self.float_options.get(&option).copied()
+    }
+
+    /// Frame distance of lossy encoding, or `None` if not set or lossless.
+    #[inline]
+    pub fn distance(&self) -> Option<f32> {
+        self.distance
+    }
+
+    #[inline]
+    pub fn lossless(&self) -> bool {
+        self.lossless
+    }
+
+    /// Distance of the extra channel, or `None` if it uses the frame distance.
+    #[inline]
+    pub fn extra_channel_distance(&self, index: u32) -> Option<f32> {
+        self.extra_channel_distances.get(&index).copied()
+    }
+
+    /// Maximum error of each integer sample in near-lossless mode, or 0 if disabled.
+    #[inline]
+    pub fn near_lossless(&self) -> u8 {
+        self.near_lossless
+    }
+
+    pub fn effort(&self) -> Option<Effort> {
+        self.option(sys::JxlEncoderFrameSettingId_JXL_ENC_FRAME_SETTING_EFFORT)
+            .and_then(|effort| Effort::try_from(effort).ok())
+    }
+
+    /// Whether Modular mode is forced, or `None` if it's chosen by libjxl.
+    pub fn modular(&self) -> Option<bool> {
+        match self.option(sys::JxlEncoderFrameSettingId_JXL_ENC_FRAME_SETTING_MODULAR) {
+            Some(0) => Some(false),
+            Some(1) => Some(true),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug)]
-pub struct FrameHeader(FrameHeaderData);
+pub struct FrameHeader(pub(crate) FrameHeaderData);
 
 impl Default for FrameHeader {
     fn default() -> Self {
@@ -234,6 +676,130 @@ impl FrameHeader {
             Self(frame_header.assume_init())
         }
     }
+
+    /// Returns a builder of frame headers, starting from the default values.
+    pub fn builder() -> FrameHeaderBuilder {
+        FrameHeaderBuilder {
+            header: Self::new(),
+            invalid: false,
+        }
+    }
+
+    /// Places the frame at the given offset of the canvas, with the given size.
+    ///
+    /// Offsets may be negative, or the frame may extend past the canvas; the part outside of the
+    /// canvas is cropped.
+    pub fn set_layer(&mut self, x0: i32, y0: i32, width: u32, height: u32) -> &mut Self {
+        let layer_info = &mut self.0.layer_info;
+        layer_info.have_crop = sys::JXL_TRUE as i32;
+        layer_info.crop_x0 = x0;
+        layer_info.crop_y0 = y0;
+        layer_info.xsize = width;
+        layer_info.ysize = height;
+        self
+    }
+
+    /// Sets how the frame is blended onto the reference frame saved in slot `source`, which is
+    /// in range of `0..=3`.
+    ///
+    /// `alpha` is the index of the extra channel used as alpha by [`BlendMode::Blend`] and
+    /// [`BlendMode::MulAdd`].
+    pub fn set_blend(&mut self, mode: BlendMode, source: u32, alpha: u32) -> Result<&mut Self> {
+        if source > 3 {
+            return Err(Error::ApiUsage);
+        }
+
+        let blend_info = &mut self.0.layer_info.blend_info;
+        blend_info.blendmode = mode.into();
+        blend_info.source = source;
+        blend_info.alpha = alpha;
+        Ok(self)
+    }
+}
+
+/// Builder of [`FrameHeader`], created with [`FrameHeader::builder`].
+///
+/// Invalid values are reported by [`build`][Self::build]. The last frame is marked by
+/// [`JxlEncoder::close_frames`][crate::JxlEncoder::close_frames] instead of the frame header,
+/// as the encoder ignores it.
+#[derive(Debug)]
+pub struct FrameHeaderBuilder {
+    header: FrameHeader,
+    invalid: bool,
+}
+
+impl FrameHeaderBuilder {
+    /// Sets how long the frame is displayed, in ticks of the animation.
+    pub fn duration(mut self, ticks: u32) -> Self {
+        self.header.0.duration = ticks;
+        self
+    }
+
+    /// Sets SMPTE timecode of the frame in form of `0xHHMMSSFF`.
+    pub fn timecode(mut self, timecode: u32) -> Self {
+        self.header.0.timecode = timecode;
+        self
+    }
+
+    /// Places the frame at the given offset of the canvas, with the given nonzero size.
+    ///
+    /// See [`FrameHeader::set_layer`].
+    pub fn layer(mut self, x0: i32, y0: i32, width: u32, height: u32) -> Self {
+        if width == 0 || height == 0 {
+            self.invalid = true;
+        }
+        self.header.set_layer(x0, y0, width, height);
+        self
+    }
+
+    /// Sets how the frame is blended. See [`FrameHeader::set_blend`].
+    pub fn blend(mut self, mode: BlendMode, source: u32, alpha: u32) -> Self {
+        if self.header.set_blend(mode, source, alpha).is_err() {
+            self.invalid = true;
+        }
+        self
+    }
+
+    /// Saves the frame after blending to reference slot `slot`, which is in range of `0..=2`.
+    ///
+    /// Slot 3 is reserved for frames generated by libjxl.
+    pub fn save_as_reference(mut self, slot: u32) -> Self {
+        if slot > 2 {
+            self.invalid = true;
+        }
+        self.header.0.layer_info.save_as_reference = slot;
+        self
+    }
+
+    /// Returns the frame header, or [`Error::ApiUsage`] if any of the values is invalid.
+    pub fn build(self) -> Result<FrameHeader> {
+        if self.invalid {
+            return Err(Error::ApiUsage);
+        }
+        Ok(self.header)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub enum BlendMode {
+    #[default]
+    Replace,
+    Add,
+    Blend,
+    MulAdd,
+    Mul,
+}
+
+impl From<BlendMode> for sys::JxlBlendMode {
+    fn from(value: BlendMode) -> Self {
+        match value {
+            BlendMode::Replace => sys::JxlBlendMode_JXL_BLEND_REPLACE,
+            BlendMode::Add => sys::JxlBlendMode_JXL_BLEND_ADD,
+            BlendMode::Blend => sys::JxlBlendMode_JXL_BLEND_BLEND,
+            BlendMode::MulAdd => sys::JxlBlendMode_JXL_BLEND_MULADD,
+            BlendMode::Mul => sys::JxlBlendMode_JXL_BLEND_MUL,
+        }
+    }
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
@@ -266,3 +832,13 @@ impl TryFrom<i64> for Effort {
         }
     }
 }
+
+/// Order of groups in the codestream, which affects progressive decoding.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+#[repr(i64)]
+pub enum GroupOrder {
+    #[default]
+    Scanline = 0,
+    /// Groups near the center come first, so that the region of interest is decoded early.
+    CenterFirst = 1,
+}
@@ -2,16 +2,33 @@ use std::mem::MaybeUninit;
 use std::ops::{Deref, DerefMut};
 use std::ptr::NonNull;
 
+#[cfg(feature = "image")]
+mod batch;
+#[cfg(feature = "image")]
+mod dynamic_image;
+mod encode;
 mod encoder_frame;
 mod error;
+mod extra_channel;
 mod frame_settings;
+mod metadata_box;
 mod parallel_runner;
 pub mod sys;
+mod version;
 
+#[cfg(feature = "image")]
+pub use batch::{BatchResult, batch_encode};
+#[cfg(feature = "image")]
+pub use dynamic_image::{color_type_format, decode_to_dynamic_image, encode_dynamic_image};
+pub use encode::{EncodeOptions, encode_rgb8, recompress};
 pub use encoder_frame::*;
 pub use error::{Error, Result};
+pub use extra_channel::{ExtraChannelInfo, ExtraChannelInfoData, ExtraChannelType};
 pub use frame_settings::*;
+pub use metadata_box::MetadataBox;
+pub use parallel_runner::CancellationToken;
 pub use sys::JxlBasicInfo as BasicInfoData;
+pub use version::{decoder_version, encoder_version, version_string};
 
 #[derive(Debug)]
 pub struct BasicInfo(BasicInfoData);
@@ -44,6 +61,227 @@ impl BasicInfo {
             Self(basic_info.assume_init())
         }
     }
+
+    /// Sets whether pixels are encoded in the original color space, instead of XYB.
+    ///
+    /// This is required for lossless encoding, as conversion to XYB is lossy.
+    pub fn set_uses_original_profile(&mut self, uses_original_profile: bool) -> &mut Self {
+        self.0.uses_original_profile = uses_original_profile as i32;
+        self
+    }
+
+    /// Sets the number of color channels, 1 for grayscale and 3 for color images.
+    ///
+    /// Grayscale images should use a grayscale color encoding, such as
+    /// [`ColorEncoding::srgb_gray`]. Returns [`Error::ApiUsage`] for other values.
+    pub fn set_num_color_channels(&mut self, num_color_channels: u32) -> Result<&mut Self> {
+        if num_color_channels != 1 && num_color_channels != 3 {
+            return Err(Error::ApiUsage);
+        }
+
+        self.0.num_color_channels = num_color_channels;
+        Ok(self)
+    }
+
+    /// Declares an alpha channel as the first extra channel, with the given bit depth.
+    ///
+    /// `premultiplied` should be set if color samples are already multiplied by alpha.
+    pub fn set_alpha(&mut self, bits_per_sample: u32, premultiplied: bool) -> &mut Self {
+        self.0.num_extra_channels = self.0.num_extra_channels.max(1);
+        self.0.alpha_bits = bits_per_sample;
+        self.0.alpha_exponent_bits = self.0.exponent_bits_per_sample;
+        self.0.alpha_premultiplied = premultiplied as i32;
+        self
+    }
+
+    /// Declares that samples are floating point numbers of the given bit depth, including sign
+    /// and exponent bits. Also applies to alpha, if set.
+    ///
+    /// The depth should fit in the sample format of the input buffer, e.g. `(16, 5)` for
+    /// [`SampleFormat::F16`] and `(32, 8)` for [`SampleFormat::F32`]. Returns
+    /// [`Error::ApiUsage`] if it cannot be represented with [`SampleFormat::F32`].
+    pub fn set_float_depth(
+        &mut self,
+        bits_per_sample: u32,
+        exponent_bits: u32,
+    ) -> Result<&mut Self> {
+        if !(2..=8).contains(&exponent_bits) {
+            return Err(Error::ApiUsage);
+        }
+        let mantissa_bits = bits_per_sample
+            .checked_sub(exponent_bits + 1)
+            .ok_or(Error::ApiUsage)?;
+        if !(2..=23).contains(&mantissa_bits) {
+            return Err(Error::ApiUsage);
+        }
+
+        self.0.bits_per_sample = bits_per_sample;
+        self.0.exponent_bits_per_sample = exponent_bits;
+        if self.0.alpha_bits != 0 {
+            self.0.alpha_bits = bits_per_sample;
+            self.0.alpha_exponent_bits = exponent_bits;
+        }
+        Ok(self)
+    }
+
+    /// Declares that the image is an animation, with frame durations counted in ticks of
+    /// `tps_denominator / tps_numerator` seconds.
+    ///
+    /// `num_loops` of 0 means the animation loops forever. Durations of each frame are set with
+    /// [`FrameHeader`]. Returns [`Error::ApiUsage`] if either part of the tick rate is zero.
+    pub fn set_animation(
+        &mut self,
+        tps_numerator: u32,
+        tps_denominator: u32,
+        num_loops: u32,
+    ) -> Result<&mut Self> {
+        if tps_numerator == 0 || tps_denominator == 0 {
+            return Err(Error::ApiUsage);
+        }
+
+        self.0.have_animation = sys::JXL_TRUE as i32;
+        self.0.animation.tps_numerator = tps_numerator;
+        self.0.animation.tps_denominator = tps_denominator;
+        self.0.animation.num_loops = num_loops;
+        Ok(self)
+    }
+
+    /// Orientation of the image, which should be applied when displaying decoded pixels.
+    ///
+    /// The decoder keeps pixels as stored, so callers should rotate or flip them as indicated.
+    /// Unknown values are treated as [`Orientation::Identity`].
+    pub fn orientation(&self) -> Orientation {
+        Orientation::from_sys(self.0.orientation).unwrap_or_default()
+    }
+
+    pub fn set_orientation(&mut self, orientation: Orientation) -> &mut Self {
+        self.0.orientation = orientation.into();
+        self
+    }
+
+    /// Sets the peak luminance of the image in nits, which is the luminance of the maximum
+    /// sample value.
+    ///
+    /// Defaults to 255 nits for SDR images. Images using the PQ transfer function should set it
+    /// to 10000 nits, as PQ encodes absolute luminance up to that. Returns
+    /// [`Error::ApiUsage`] if `nits` is not positive and finite.
+    pub fn set_intensity_target(&mut self, nits: f32) -> Result<&mut Self> {
+        if !nits.is_finite() || nits <= 0. {
+            return Err(Error::ApiUsage);
+        }
+        self.0.intensity_target = nits;
+        Ok(self)
+    }
+
+    /// Sets tone mapping metadata, used by decoders to map the image to displays with lower
+    /// dynamic range.
+    ///
+    /// Returns [`Error::ApiUsage`] if `intensity_target` is not positive, `min_nits` is not
+    /// within `0..=intensity_target`, or `linear_below` is negative (or greater than 1 if
+    /// relative to the maximum display luminance).
+    pub fn set_tone_mapping(&mut self, tone_mapping: &ToneMappingInfo) -> Result<&mut Self> {
+        let ToneMappingInfo {
+            intensity_target,
+            min_nits,
+            relative_to_max_display,
+            linear_below,
+        } = *tone_mapping;
+        let max_linear_below = if relative_to_max_display {
+            1.
+        } else {
+            intensity_target
+        };
+        if !(0. ..=intensity_target).contains(&min_nits)
+            || !(0. ..=max_linear_below).contains(&linear_below)
+        {
+            return Err(Error::ApiUsage);
+        }
+
+        self.set_intensity_target(intensity_target)?;
+        self.0.min_nits = min_nits;
+        self.0.relative_to_max_display = relative_to_max_display as i32;
+        self.0.linear_below = linear_below;
+        Ok(self)
+    }
+
+    pub fn tone_mapping(&self) -> ToneMappingInfo {
+        ToneMappingInfo {
+            intensity_target: self.0.intensity_target,
+            min_nits: self.0.min_nits,
+            relative_to_max_display: self.0.relative_to_max_display != 0,
+            linear_below: self.0.linear_below,
+        }
+    }
+}
+
+/// Image orientation, with the same values as the Exif orientation tag.
+///
+/// Each variant describes how stored pixels should be transformed to be displayed upright.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub enum Orientation {
+    #[default]
+    Identity,
+    FlipHorizontal,
+    Rotate180,
+    FlipVertical,
+    Transpose,
+    Rotate90Cw,
+    AntiTranspose,
+    Rotate90Ccw,
+}
+
+impl Orientation {
+    pub fn from_sys(value: sys::JxlOrientation) -> Option<Self> {
+        Some(match value {
+            sys::JxlOrientation_JXL_ORIENT_IDENTITY => Self::Identity,
+            sys::JxlOrientation_JXL_ORIENT_FLIP_HORIZONTAL => Self::FlipHorizontal,
+            sys::JxlOrientation_JXL_ORIENT_ROTATE_180 => Self::Rotate180,
+            sys::JxlOrientation_JXL_ORIENT_FLIP_VERTICAL => Self::FlipVertical,
+            sys::JxlOrientation_JXL_ORIENT_TRANSPOSE => Self::Transpose,
+            sys::JxlOrientation_JXL_ORIENT_ROTATE_90_CW => Self::Rotate90Cw,
+            sys::JxlOrientation_JXL_ORIENT_ANTI_TRANSPOSE => Self::AntiTranspose,
+            sys::JxlOrientation_JXL_ORIENT_ROTATE_90_CCW => Self::Rotate90Ccw,
+            _ => return None,
+        })
+    }
+
+    /// Whether width and height are swapped when displayed.
+    #[inline]
+    pub fn swaps_axes(self) -> bool {
+        matches!(
+            self,
+            Self::Transpose | Self::Rotate90Cw | Self::AntiTranspose | Self::Rotate90Ccw
+        )
+    }
+}
+
+impl From<Orientation> for sys::JxlOrientation {
+    fn from(value: Orientation) -> Self {
+        match value {
+            Orientation::Identity => sys::JxlOrientation_JXL_ORIENT_IDENTITY,
+            Orientation::FlipHorizontal => sys::JxlOrientation_JXL_ORIENT_FLIP_HORIZONTAL,
+            Orientation::Rotate180 => sys::JxlOrientation_JXL_ORIENT_ROTATE_180,
+            Orientation::FlipVertical => sys::JxlOrientation_JXL_ORIENT_FLIP_VERTICAL,
+            Orientation::Transpose => sys::JxlOrientation_JXL_ORIENT_TRANSPOSE,
+            Orientation::Rotate90Cw => sys::JxlOrientation_JXL_ORIENT_ROTATE_90_CW,
+            Orientation::AntiTranspose => sys::JxlOrientation_JXL_ORIENT_ANTI_TRANSPOSE,
+            Orientation::Rotate90Ccw => sys::JxlOrientation_JXL_ORIENT_ROTATE_90_CCW,
+        }
+    }
+}
+
+/// Tone mapping metadata of HDR images.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ToneMappingInfo {
+    /// Upper bound of the luminance of the image, in nits.
+    pub intensity_target: f32,
+    /// Lower bound of the luminance of the image, in nits.
+    pub min_nits: f32,
+    /// Whether `linear_below` is a ratio to the maximum display luminance, rather than an
+    /// absolute value in nits.
+    pub relative_to_max_display: bool,
+    /// Luminance below which tone mapping should keep the image linear.
+    pub linear_below: f32,
 }
 
 #[derive(Debug)]
@@ -79,6 +317,58 @@ impl ColorEncoding {
             rendering_intent: intent.into(),
         })
     }
+
+    /// Grayscale with the sRGB transfer function, for images with a single color channel.
+    pub fn srgb_gray(intent: RenderingIntent) -> Self {
+        let mut color_encoding = Self::srgb(intent);
+        color_encoding.0.color_space = sys::JxlColorSpace_JXL_COLOR_SPACE_GRAY;
+        color_encoding
+    }
+
+    /// Grayscale with the linear transfer function, for images with a single color channel.
+    pub fn srgb_gray_linear(intent: RenderingIntent) -> Self {
+        let mut color_encoding = Self::srgb_linear(intent);
+        color_encoding.0.color_space = sys::JxlColorSpace_JXL_COLOR_SPACE_GRAY;
+        color_encoding
+    }
+
+    fn bt2100(transfer_function: sys::JxlTransferFunction, intent: RenderingIntent) -> Self {
+        Self(sys::JxlColorEncoding {
+            color_space: sys::JxlColorSpace_JXL_COLOR_SPACE_RGB,
+            white_point: sys::JxlWhitePoint_JXL_WHITE_POINT_D65,
+            white_point_xy: Default::default(),
+            primaries: sys::JxlPrimaries_JXL_PRIMARIES_2100,
+            primaries_red_xy: Default::default(),
+            primaries_green_xy: Default::default(),
+            primaries_blue_xy: Default::default(),
+            transfer_function,
+            gamma: Default::default(),
+            rendering_intent: intent.into(),
+        })
+    }
+
+    /// BT.2100 primaries with the PQ transfer function.
+    ///
+    /// Intensity target should be set to 10000 nits with [`BasicInfo::set_intensity_target`].
+    pub fn bt2100_pq(intent: RenderingIntent) -> Self {
+        Self::bt2100(sys::JxlTransferFunction_JXL_TRANSFER_FUNCTION_PQ, intent)
+    }
+
+    /// BT.2100 primaries with the HLG transfer function.
+    ///
+    /// Intensity target should be set to the peak luminance of the mastering display.
+    pub fn bt2100_hlg(intent: RenderingIntent) -> Self {
+        Self::bt2100(sys::JxlTransferFunction_JXL_TRANSFER_FUNCTION_HLG, intent)
+    }
+
+    /// Whether the transfer function is PQ or HLG.
+    pub fn is_hdr(&self) -> bool {
+        matches!(
+            self.0.transfer_function,
+            sys::JxlTransferFunction_JXL_TRANSFER_FUNCTION_PQ
+                | sys::JxlTransferFunction_JXL_TRANSFER_FUNCTION_HLG
+        )
+    }
 }
 
 #[derive(Debug)]
@@ -103,35 +393,142 @@ impl From<RenderingIntent> for sys::JxlRenderingIntent {
 #[derive(Debug)]
 pub struct JxlEncoder {
     encoder: NonNull<sys::JxlEncoder>,
+    /// Frame settings owned by the encoder, which are freed only on drop or reset.
     frame_settings: Vec<NonNull<sys::JxlEncoderFrameSettings>>,
+    /// Values set to each of `frame_settings`.
+    frame_settings_values: Vec<FrameSettingsValues>,
+    /// Incremented on reset, to invalidate existing frame settings keys.
+    generation: u64,
     close_state: CloseState,
+    boxes_open: bool,
+    /// Whether the basic info set last has `uses_original_profile` enabled.
+    pub(crate) uses_original_profile: bool,
+    runner: sys::JxlParallelRunner,
+    /// Passed to the parallel runner, boxed to keep its address stable.
+    runner_context: Box<parallel_runner::RunnerContext>,
+    progress_callback: Option<ProgressCallback>,
+    /// Total bytes written by [`JxlEncoder::pull_outputs`].
+    bytes_produced: u64,
+}
+
+struct ProgressCallback(Box<dyn FnMut(u64) + Send>);
+
+impl std::fmt::Debug for ProgressCallback {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ProgressCallback")
+    }
 }
 
 impl JxlEncoder {
+    /// Creates an encoder which runs tasks in the global rayon thread pool.
     pub fn new() -> Option<Self> {
+        Self::with_runner(Some(parallel_runner::rayon_parallel_runner))
+    }
+
+    /// Creates an encoder which runs all tasks in the calling thread.
+    ///
+    /// This avoids contention when images are already encoded in parallel, one per thread.
+    pub fn new_single_threaded() -> Option<Self> {
+        Self::with_runner(Some(parallel_runner::serial_parallel_runner))
+    }
+
+    fn with_runner(runner: sys::JxlParallelRunner) -> Option<Self> {
+        let encoder = unsafe { sys::JxlEncoderCreate(std::ptr::null_mut()) };
+        let encoder = NonNull::new(encoder)?;
+        let mut this = Self {
+            encoder,
+            frame_settings: Vec::new(),
+            frame_settings_values: Vec::new(),
+            generation: 0,
+            close_state: CloseState::Open,
+            boxes_open: false,
+            uses_original_profile: false,
+            runner,
+            runner_context: Box::default(),
+            progress_callback: None,
+            bytes_produced: 0,
+        };
+        this.set_parallel_runner();
+        Some(this)
+    }
+
+    fn set_parallel_runner(&mut self) {
         unsafe {
-            let encoder = sys::JxlEncoderCreate(std::ptr::null_mut());
             sys::JxlEncoderSetParallelRunner(
-                encoder,
-                Some(parallel_runner::rayon_parallel_runner),
-                std::ptr::null_mut(),
+                self.encoder.as_ptr(),
+                self.runner,
+                (&mut *self.runner_context as *mut parallel_runner::RunnerContext).cast(),
             );
-            let encoder = NonNull::new(encoder)?;
-            Some(Self {
-                encoder,
-                frame_settings: Vec::new(),
-                close_state: CloseState::Open,
-            })
         }
     }
 
+    /// Resets the encoder to the newly created state, so that it can be reused for another image.
+    ///
+    /// All frame settings are freed, and existing keys to them become invalid.
+    pub fn reset(&mut self) {
+        unsafe {
+            sys::JxlEncoderReset(self.encoder.as_ptr());
+        }
+        // Parallel runner is reset along with other settings.
+        self.set_parallel_runner();
+        self.frame_settings.clear();
+        self.frame_settings_values.clear();
+        self.generation += 1;
+        self.close_state = CloseState::Open;
+        self.boxes_open = false;
+        self.uses_original_profile = false;
+        self.bytes_produced = 0;
+    }
+
+    /// Sets the token used to cancel encoding from another thread.
+    ///
+    /// Once cancelled, [`pull_outputs`][Self::pull_outputs] returns [`Error::Cancelled`] as
+    /// soon as running tasks finish, and the encoder should be reset or dropped.
+    pub fn set_cancellation_token(&mut self, token: Option<CancellationToken>) {
+        self.runner_context.cancellation = token;
+    }
+
+    /// Sets the callback invoked with the total number of bytes produced so far, each time
+    /// [`pull_outputs`][Self::pull_outputs] writes output.
+    ///
+    /// libjxl encodes most of the image before producing the first output, so progress is
+    /// coarse.
+    pub fn set_progress_callback(&mut self, callback: impl FnMut(u64) + Send + 'static) {
+        self.progress_callback = Some(ProgressCallback(Box::new(callback)));
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.runner_context
+            .cancellation
+            .as_ref()
+            .is_some_and(CancellationToken::is_cancelled)
+    }
+
+    /// Sets basic image information.
+    ///
+    /// Returns [`Error::BadInput`] if either of the dimensions is zero.
     pub fn set_basic_info(&mut self, basic_info: &BasicInfo) -> Result<()> {
+        if basic_info.xsize == 0 || basic_info.ysize == 0 {
+            return Err(Error::BadInput);
+        }
+
         unsafe {
             let _ret = sys::JxlEncoderSetBasicInfo(self.encoder.as_ptr(), &basic_info.0);
-            Error::try_from_libjxl_encoder(self.encoder)
+            Error::try_from_libjxl_encoder(self.encoder)?;
         }
+        self.uses_original_profile = basic_info.uses_original_profile != 0;
+        Ok(())
     }
 
+    /// Sets color encoding of the image.
+    ///
+    /// Color encoding is stored in the image header, and applies to all frames; JPEG XL doesn't
+    /// support per-frame color encoding. Color encoding can be set only once, either with this
+    /// or [`set_icc_profile`][Self::set_icc_profile], and setting it again returns
+    /// [`Error::ApiUsage`].
+    ///
+    /// libjxl doesn't expose the ICC profile derived from the color encoding in the encoder. It
+    /// can be read from the output with [`JxlDecoder::icc_profile`].
     pub fn set_color_encoding(&mut self, color_encoding: &ColorEncoding) -> Result<()> {
         unsafe {
             let _ret = sys::JxlEncoderSetColorEncoding(self.encoder.as_ptr(), &color_encoding.0);
@@ -139,6 +536,9 @@ impl JxlEncoder {
         }
     }
 
+    /// Sets color encoding of the image using an ICC profile.
+    ///
+    /// See [`set_color_encoding`][Self::set_color_encoding] for restrictions.
     pub fn set_icc_profile(&mut self, icc: &[u8]) -> Result<()> {
         unsafe {
             let _ret = sys::JxlEncoderSetICCProfile(self.encoder.as_ptr(), icc.as_ptr(), icc.len());
@@ -146,6 +546,44 @@ impl JxlEncoder {
         }
     }
 
+    /// Sets information of the extra channel at the given index, which should be smaller than
+    /// `num_extra_channels` of the basic info.
+    pub fn set_extra_channel_info(&mut self, index: u32, info: &ExtraChannelInfo) -> Result<()> {
+        unsafe {
+            let _ret =
+                sys::JxlEncoderSetExtraChannelInfo(self.encoder.as_ptr(), index as usize, &info.0);
+            Error::try_from_libjxl_encoder(self.encoder)
+        }
+    }
+
+    pub fn set_extra_channel_name(&mut self, index: u32, name: &str) -> Result<()> {
+        unsafe {
+            let _ret = sys::JxlEncoderSetExtraChannelName(
+                self.encoder.as_ptr(),
+                index as usize,
+                name.as_ptr().cast(),
+                name.len(),
+            );
+            Error::try_from_libjxl_encoder(self.encoder)
+        }
+    }
+
+    /// Sets codestream level, either 5 or 10. `None` chooses the lowest level which supports the
+    /// image.
+    ///
+    /// Level 10 is required for CMYK images, and large images or high bit depths.
+    pub fn set_codestream_level(&mut self, level: Option<u32>) -> Result<()> {
+        let level = match level {
+            None => -1,
+            Some(level @ (5 | 10)) => level as i32,
+            Some(_) => return Err(Error::ApiUsage),
+        };
+        unsafe {
+            let _ret = sys::JxlEncoderSetCodestreamLevel(self.encoder.as_ptr(), level);
+            Error::try_from_libjxl_encoder(self.encoder)
+        }
+    }
+
     pub fn set_jpeg_reconstruction(&mut self, store_jpeg_metadata: bool) -> Result<()> {
         let store_jpeg_metadata = if store_jpeg_metadata {
             sys::JXL_TRUE
@@ -159,6 +597,28 @@ impl JxlEncoder {
         }
     }
 
+    /// Sets whether the codestream is wrapped in the ISO BMFF based container.
+    ///
+    /// Without it, the output is a bare codestream unless the container is required. The
+    /// container is always used if metadata boxes are used, JPEG reconstruction data is stored,
+    /// or codestream level 10 is set. This should be called before any output is produced.
+    pub fn use_container(&mut self, use_container: bool) -> Result<()> {
+        let use_container = if use_container {
+            sys::JXL_TRUE
+        } else {
+            sys::JXL_FALSE
+        };
+        unsafe {
+            let _ret = sys::JxlEncoderUseContainer(self.encoder.as_ptr(), use_container as i32);
+            Error::try_from_libjxl_encoder(self.encoder)
+        }
+    }
+
+    /// Creates new frame settings, and returns the key to them.
+    ///
+    /// Frame settings are owned by the encoder until it's dropped or [reset][Self::reset], so
+    /// creating them repeatedly for a long-lived encoder grows its memory usage. Use
+    /// [`update_frame_settings_with`][Self::update_frame_settings_with] to reuse existing ones.
     pub fn create_frame_settings_with<'encoder>(
         &'encoder mut self,
         f: impl FnOnce(&mut FrameSettings<'encoder>) -> Result<()>,
@@ -188,10 +648,87 @@ impl JxlEncoder {
         Ok(())
     }
 
-    pub fn add_frame(&mut self, settings_key: FrameSettingsKey) -> Result<EncoderFrame> {
+    /// Returns values set to the frame settings.
+    pub fn frame_settings_values(
+        &self,
+        settings_key: FrameSettingsKey,
+    ) -> Result<&FrameSettingsValues> {
+        settings_key.try_index_values(self)
+    }
+
+    /// Adds a new frame using the given frame settings.
+    ///
+    /// Frames of an image are encoded sequentially. Encoding frames in separate encoders
+    /// concurrently and concatenating the outputs doesn't produce a valid image: each output is a
+    /// complete codestream with its own image header, and the last frame of each is marked as the
+    /// last frame of the image in its frame header, which libjxl doesn't allow rewriting.
+    /// Encoding of a single frame is already parallelized by the rayon thread pool.
+    ///
+    /// To encode multiple frames, call this for each frame, then call
+    /// [`close_frames`][Self::close_frames] (or [`close_input`][Self::close_input]) after adding
+    /// the last one, so that it's marked as the last frame. Frames cannot be added after closing.
+    pub fn add_frame(&mut self, settings_key: FrameSettingsKey) -> Result<EncoderFrame<'_>> {
         EncoderFrame::new(self, settings_key)
     }
 
+    /// Adds pixel frames in order using the same frame settings, and closes frames.
+    ///
+    /// Outputs can be pulled after this returns, unless boxes are still open.
+    pub fn add_image_frames<'buf>(
+        &mut self,
+        settings_key: FrameSettingsKey,
+        num_channels: u32,
+        sample_format: SampleFormat,
+        frames: impl IntoIterator<Item = &'buf [u8]>,
+    ) -> Result<()> {
+        for buffer in frames {
+            self.add_frame(settings_key)?
+                .color_channels(num_channels, sample_format, buffer)?;
+        }
+        self.close_frames();
+        Ok(())
+    }
+
+    /// Declares that metadata boxes will be added with [`add_box`][Self::add_box].
+    ///
+    /// This should be called before any output is produced, and boxes must be closed with
+    /// [`close_boxes`][Self::close_boxes] or [`close_input`][Self::close_input] later.
+    pub fn use_boxes(&mut self) -> Result<()> {
+        unsafe {
+            let _ret = sys::JxlEncoderUseBoxes(self.encoder.as_ptr());
+            Error::try_from_libjxl_encoder(self.encoder)?;
+        }
+        self.boxes_open = true;
+        Ok(())
+    }
+
+    /// Adds a metadata box, compressing it if [`MetadataBox::is_compressed`] is set.
+    pub fn add_box(&mut self, metadata_box: &MetadataBox) -> Result<()> {
+        if MetadataBox::is_reserved_type(metadata_box.box_type()) {
+            return Err(Error::ApiUsage);
+        }
+
+        let box_type = metadata_box.box_type().map(|c| c as std::ffi::c_char);
+        let contents = metadata_box.contents();
+        unsafe {
+            let _ret = sys::JxlEncoderAddBox(
+                self.encoder.as_ptr(),
+                box_type.as_ptr(),
+                contents.as_ptr(),
+                contents.len(),
+                metadata_box.is_compressed() as i32,
+            );
+            Error::try_from_libjxl_encoder(self.encoder)
+        }
+    }
+
+    pub fn close_boxes(&mut self) {
+        unsafe {
+            sys::JxlEncoderCloseBoxes(self.encoder.as_ptr());
+        }
+        self.boxes_open = false;
+    }
+
     pub fn close_frames(&mut self) {
         unsafe {
             sys::JxlEncoderCloseFrames(self.encoder.as_ptr());
@@ -204,9 +741,21 @@ impl JxlEncoder {
             sys::JxlEncoderCloseInput(self.encoder.as_ptr());
             self.close_state = CloseState::InputClosed;
         }
+        self.boxes_open = false;
     }
 
+    /// Writes encoded output to the buffer.
+    ///
+    /// Frames must be closed with [`close_frames`][Self::close_frames] or
+    /// [`close_input`][Self::close_input] before pulling outputs, and so must boxes if
+    /// [`use_boxes`][Self::use_boxes] was called. Otherwise the encoder would never finish, and
+    /// [`Error::ApiUsage`] is returned instead.
     pub fn pull_outputs(&mut self, buffer: &mut [u8]) -> Result<OutputStatus> {
+        if self.close_state == CloseState::Open || self.boxes_open {
+            // Input not closed
+            return Err(Error::ApiUsage);
+        }
+
         let mut bytes_avail = buffer.len();
         if bytes_avail < 32 {
             return Ok(OutputStatus {
@@ -219,6 +768,10 @@ impl JxlEncoder {
         let mut need_more_output = true;
         unsafe {
             while bytes_avail >= 32 {
+                if self.is_cancelled() {
+                    return Err(Error::Cancelled);
+                }
+
                 let ret = sys::JxlEncoderProcessOutput(
                     self.encoder.as_ptr(),
                     &mut buffer_ptr,
@@ -229,6 +782,9 @@ impl JxlEncoder {
                     break;
                 }
                 if ret == sys::JxlEncoderStatus_JXL_ENC_ERROR {
+                    if self.is_cancelled() {
+                        return Err(Error::Cancelled);
+                    }
                     Error::try_from_libjxl_encoder(self.encoder)?;
                     // Fallback error code
                     return Err(Error::BadInput);
@@ -236,11 +792,39 @@ impl JxlEncoder {
             }
         }
 
+        let bytes_written = buffer.len() - bytes_avail;
+        self.bytes_produced += bytes_written as u64;
+        if bytes_written > 0
+            && let Some(callback) = &mut self.progress_callback
+        {
+            (callback.0)(self.bytes_produced);
+        }
+
         Ok(OutputStatus {
-            bytes_written: buffer.len() - bytes_avail,
+            bytes_written,
             need_more_output,
         })
     }
+
+    /// Returns an iterator over chunks of encoded output, pulled using
+    /// [`pull_outputs`][Self::pull_outputs] until the encoder is done.
+    ///
+    /// Chunks are at most 1 MiB long.
+    pub fn output_chunks(&mut self) -> OutputChunks<'_> {
+        self.output_chunks_with_size(OutputChunks::DEFAULT_CHUNK_SIZE)
+    }
+
+    /// Returns an iterator over chunks of encoded output, each at most `chunk_size` bytes long.
+    ///
+    /// Smaller chunks use less memory, and larger chunks need fewer calls into libjxl. Chunk
+    /// size is at least 32 bytes, as libjxl cannot produce output in smaller buffers.
+    pub fn output_chunks_with_size(&mut self, chunk_size: usize) -> OutputChunks<'_> {
+        OutputChunks {
+            encoder: self,
+            buffer: vec![0u8; chunk_size.max(OutputChunks::MIN_CHUNK_SIZE)],
+            done: false,
+        }
+    }
 }
 
 impl Drop for JxlEncoder {
@@ -275,47 +859,513 @@ impl OutputStatus {
     }
 }
 
+/// Iterator returned by [`JxlEncoder::output_chunks`].
+///
+/// The iterator stops after the last chunk, or after yielding an error.
+#[derive(Debug)]
+pub struct OutputChunks<'encoder> {
+    encoder: &'encoder mut JxlEncoder,
+    buffer: Vec<u8>,
+    done: bool,
+}
+
+impl OutputChunks<'_> {
+    const DEFAULT_CHUNK_SIZE: usize = 1024 * 1024;
+    const MIN_CHUNK_SIZE: usize = 32;
+}
+
+impl Iterator for OutputChunks<'_> {
+    type Item = Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        match self.encoder.pull_outputs(&mut self.buffer) {
+            Ok(status) => {
+                self.done = !status.need_more_output();
+                let chunk = self.buffer[..status.bytes_written()].to_vec();
+                if chunk.is_empty() && self.done {
+                    return None;
+                }
+                Some(Ok(chunk))
+            }
+            Err(err) => {
+                self.done = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+impl std::iter::FusedIterator for OutputChunks<'_> {}
+
+/// Whether pixels with `num_channels` channels can be decoded from an image with
+/// `num_color_channels` color channels.
+fn is_compatible_num_channels(num_channels: u32, num_color_channels: u32) -> bool {
+    match num_channels {
+        1 | 2 => num_color_channels == 1,
+        3 | 4 => true,
+        _ => false,
+    }
+}
+
+/// Type of JPEG XL signature found at the beginning of a file.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Signature {
+    /// Not enough bytes were given to determine the signature.
+    NotEnoughBytes,
+    Invalid,
+    /// Bare codestream without container.
+    Codestream,
+    /// Codestream in a BMFF container.
+    Container,
+}
+
+/// Checks if the buffer starts with a JPEG XL signature.
+pub fn check_signature(buf: &[u8]) -> Signature {
+    let ret = unsafe { sys::JxlSignatureCheck(buf.as_ptr(), buf.len()) };
+    match ret {
+        sys::JxlSignature_JXL_SIG_NOT_ENOUGH_BYTES => Signature::NotEnoughBytes,
+        sys::JxlSignature_JXL_SIG_CODESTREAM => Signature::Codestream,
+        sys::JxlSignature_JXL_SIG_CONTAINER => Signature::Container,
+        _ => Signature::Invalid,
+    }
+}
+
+/// JPEG XL decoder.
+///
+/// The decoder can be moved to another thread, e.g. to be held across `.await` points, but it
+/// can only be used by one thread at a time, which is guaranteed as all methods take
+/// `&mut self`.
 #[derive(Debug)]
 pub struct JxlDecoder {
     decoder: NonNull<sys::JxlDecoder>,
+    runner: sys::JxlParallelRunner,
+    /// Decoded pixels borrowed by [`RowIter`].
+    rows: Vec<u8>,
+    /// Peak display luminance to tone map to, or 0 to disable tone mapping.
+    desired_intensity_target: f32,
+    /// Whether spot colors are composited onto decoded color channels.
+    render_spot_colors: bool,
+    /// Range of decoded integer samples.
+    image_out_bit_depth: BitDepth,
 }
 
 impl JxlDecoder {
+    /// Creates a decoder which runs tasks in the global rayon thread pool.
     pub fn new() -> Option<Self> {
+        Self::with_runner(Some(parallel_runner::rayon_parallel_runner))
+    }
+
+    /// Creates a decoder which runs all tasks in the calling thread.
+    pub fn new_single_threaded() -> Option<Self> {
+        Self::with_runner(Some(parallel_runner::serial_parallel_runner))
+    }
+
+    fn with_runner(runner: sys::JxlParallelRunner) -> Option<Self> {
+        let decoder = unsafe { sys::JxlDecoderCreate(std::ptr::null_mut()) };
+        let decoder = NonNull::new(decoder)?;
+        let mut this = Self {
+            decoder,
+            runner,
+            rows: Vec::new(),
+            desired_intensity_target: 0.,
+            render_spot_colors: true,
+            image_out_bit_depth: BitDepth::FromPixelFormat,
+        };
+        this.reset();
+        Some(this)
+    }
+
+    /// Resets the decoder, and sets the parallel runner again as it's also reset.
+    ///
+    /// Other decoder options are cleared too, so they're kept in fields and applied on each
+    /// decode.
+    fn reset(&mut self) {
         unsafe {
-            let decoder = sys::JxlDecoderCreate(std::ptr::null_mut());
+            sys::JxlDecoderReset(self.decoder.as_ptr());
             sys::JxlDecoderSetParallelRunner(
-                decoder,
-                Some(parallel_runner::rayon_parallel_runner),
+                self.decoder.as_ptr(),
+                self.runner,
                 std::ptr::null_mut(),
             );
-            let decoder = NonNull::new(decoder)?;
-            Some(Self { decoder })
         }
     }
 
+    /// Sets the peak display luminance in nits, which libjxl tone maps decoded pixels to if the
+    /// image is brighter. Value of 0 disables tone mapping, which is the default.
+    ///
+    /// Returns [`Error::ApiUsage`] if the value is negative.
+    pub fn set_desired_intensity_target(&mut self, intensity_target: f32) -> Result<()> {
+        if intensity_target.is_nan() || intensity_target < 0. {
+            return Err(Error::ApiUsage);
+        }
+        self.desired_intensity_target = intensity_target;
+        Ok(())
+    }
+
+    /// Sets whether spot color channels are composited onto decoded color channels, which is
+    /// the default.
+    ///
+    /// Disable it to get the color channels without spot colors, for example to edit each
+    /// plate separately.
+    pub fn set_render_spot_colors(&mut self, render_spot_colors: bool) {
+        self.render_spot_colors = render_spot_colors;
+    }
+
+    /// Sets the range of decoded integer samples, which is the full range of the sample format
+    /// by default.
+    ///
+    /// For example, samples of a 10-bit image decoded to [`SampleFormat::U16`] are in range of
+    /// `0..=1023` with [`BitDepth::FromCodestream`]. Float samples only support
+    /// [`BitDepth::FromPixelFormat`]; decoding returns [`Error::ApiUsage`] otherwise.
+    pub fn set_image_out_bit_depth(&mut self, bit_depth: BitDepth) {
+        self.image_out_bit_depth = bit_depth;
+    }
+
+    /// Reads basic info as stored in the image header.
+    ///
+    /// Orientation is not applied to the dimension, matching pixels returned by
+    /// [`decode_to_pixels`][Self::decode_to_pixels]. Use [`BasicInfo::orientation`] to find out
+    /// how to display them.
+    pub fn basic_info(&mut self, input_buf: &[u8]) -> Result<BasicInfo> {
+        let dec = self.decoder.as_ptr();
+
+        unsafe {
+            self.reset();
+
+            let ret = sys::JxlDecoderSubscribeEvents(
+                dec,
+                sys::JxlDecoderStatus_JXL_DEC_BASIC_INFO as i32,
+            );
+            Error::try_from_libjxl_decoder(ret)?;
+
+            let ret = sys::JxlDecoderSetKeepOrientation(dec, sys::JXL_TRUE as i32);
+            Error::try_from_libjxl_decoder(ret)?;
+
+            let ret = sys::JxlDecoderSetInput(dec, input_buf.as_ptr(), input_buf.len());
+            Error::try_from_libjxl_decoder(ret)?;
+
+            let ret = sys::JxlDecoderProcessInput(dec);
+            match ret {
+                sys::JxlDecoderStatus_JXL_DEC_BASIC_INFO => {}
+                sys::JxlDecoderStatus_JXL_DEC_ERROR => return Err(Error::BadInput),
+                sys::JxlDecoderStatus_JXL_DEC_NEED_MORE_INPUT => {
+                    return Err(Error::TruncatedInput);
+                }
+                _ => return Err(Error::Libjxl(ret as i32)),
+            }
+
+            let mut basic_info = MaybeUninit::uninit();
+            let ret = sys::JxlDecoderGetBasicInfo(dec, basic_info.as_mut_ptr());
+            Error::try_from_libjxl_decoder(ret)?;
+
+            sys::JxlDecoderReleaseInput(dec);
+
+            Ok(BasicInfo(basic_info.assume_init()))
+        }
+    }
+
+    /// Reads the ICC profile of the image, as set in the encoder.
+    ///
+    /// If color encoding is set without an ICC profile, the profile is synthesized from the
+    /// color encoding.
+    pub fn icc_profile(&mut self, input_buf: &[u8]) -> Result<Vec<u8>> {
+        let target = sys::JxlColorProfileTarget_JXL_COLOR_PROFILE_TARGET_ORIGINAL;
+        self.icc_profile_inner(input_buf, target)
+    }
+
+    /// Reads the ICC profile of decoded pixels, which differs from the original profile if
+    /// the image is stored in XYB.
+    pub(crate) fn data_icc_profile(&mut self, input_buf: &[u8]) -> Result<Vec<u8>> {
+        let target = sys::JxlColorProfileTarget_JXL_COLOR_PROFILE_TARGET_DATA;
+        self.icc_profile_inner(input_buf, target)
+    }
+
+    fn icc_profile_inner(
+        &mut self,
+        input_buf: &[u8],
+        target: sys::JxlColorProfileTarget,
+    ) -> Result<Vec<u8>> {
+        let dec = self.decoder.as_ptr();
+
+        unsafe {
+            self.reset();
+
+            let ret = sys::JxlDecoderSubscribeEvents(
+                dec,
+                sys::JxlDecoderStatus_JXL_DEC_COLOR_ENCODING as i32,
+            );
+            Error::try_from_libjxl_decoder(ret)?;
+
+            let ret = sys::JxlDecoderSetInput(dec, input_buf.as_ptr(), input_buf.len());
+            Error::try_from_libjxl_decoder(ret)?;
+
+            let ret = sys::JxlDecoderProcessInput(dec);
+            match ret {
+                sys::JxlDecoderStatus_JXL_DEC_COLOR_ENCODING => {}
+                sys::JxlDecoderStatus_JXL_DEC_ERROR => return Err(Error::BadInput),
+                sys::JxlDecoderStatus_JXL_DEC_NEED_MORE_INPUT => {
+                    return Err(Error::TruncatedInput);
+                }
+                _ => return Err(Error::Libjxl(ret as i32)),
+            }
+
+            let mut size = 0usize;
+            let ret = sys::JxlDecoderGetICCProfileSize(dec, target, &mut size);
+            Error::try_from_libjxl_decoder(ret)?;
+
+            let mut icc = vec![0u8; size];
+            let ret = sys::JxlDecoderGetColorAsICCProfile(dec, target, icc.as_mut_ptr(), size);
+            Error::try_from_libjxl_decoder(ret)?;
+
+            sys::JxlDecoderReleaseInput(dec);
+
+            Ok(icc)
+        }
+    }
+
+    /// Reads info of all extra channels, in the order of their indices.
+    pub fn extra_channel_infos(&mut self, input_buf: &[u8]) -> Result<Vec<ExtraChannelInfo>> {
+        let dec = self.decoder.as_ptr();
+        let basic_info = self.basic_info(input_buf)?;
+
+        // Decoder stays at the basic info event, so extra channel info is available.
+        let mut infos = Vec::with_capacity(basic_info.num_extra_channels as usize);
+        for index in 0..basic_info.num_extra_channels {
+            let mut info = MaybeUninit::uninit();
+            unsafe {
+                let ret =
+                    sys::JxlDecoderGetExtraChannelInfo(dec, index as usize, info.as_mut_ptr());
+                Error::try_from_libjxl_decoder(ret)?;
+                infos.push(ExtraChannelInfo(info.assume_init()));
+            }
+        }
+        Ok(infos)
+    }
+
+    /// Reads headers of all frames, including frames which are not displayed by themselves.
+    pub fn frame_headers(&mut self, input_buf: &[u8]) -> Result<Vec<FrameHeader>> {
+        let dec = self.decoder.as_ptr();
+
+        unsafe {
+            self.reset();
+
+            let ret =
+                sys::JxlDecoderSubscribeEvents(dec, sys::JxlDecoderStatus_JXL_DEC_FRAME as i32);
+            Error::try_from_libjxl_decoder(ret)?;
+
+            let ret = sys::JxlDecoderSetCoalescing(dec, sys::JXL_FALSE as i32);
+            Error::try_from_libjxl_decoder(ret)?;
+
+            let ret = sys::JxlDecoderSetInput(dec, input_buf.as_ptr(), input_buf.len());
+            Error::try_from_libjxl_decoder(ret)?;
+            sys::JxlDecoderCloseInput(dec);
+
+            let mut frame_headers = Vec::new();
+            loop {
+                let ret = sys::JxlDecoderProcessInput(dec);
+                match ret {
+                    sys::JxlDecoderStatus_JXL_DEC_SUCCESS => break,
+                    sys::JxlDecoderStatus_JXL_DEC_ERROR => return Err(Error::BadInput),
+                    sys::JxlDecoderStatus_JXL_DEC_NEED_MORE_INPUT => {
+                        return Err(Error::TruncatedInput);
+                    }
+                    sys::JxlDecoderStatus_JXL_DEC_FRAME => {
+                        let mut frame_header = MaybeUninit::uninit();
+                        let ret = sys::JxlDecoderGetFrameHeader(dec, frame_header.as_mut_ptr());
+                        Error::try_from_libjxl_decoder(ret)?;
+                        frame_headers.push(FrameHeader(frame_header.assume_init()));
+                    }
+                    _ => {}
+                }
+            }
+
+            sys::JxlDecoderReleaseInput(dec);
+
+            Ok(frame_headers)
+        }
+    }
+
+    /// Decodes the image to pixels with the given number of channels.
+    ///
+    /// `num_channels` is 1 or 2 for grayscale images, and 3 or 4 for color images, where the
+    /// latter includes alpha. Alpha is dropped if not requested, and set to opaque if the image
+    /// doesn't have it. Grayscale images can be decoded to color, but color images cannot be
+    /// decoded to grayscale; [`Error::ApiUsage`] is returned in that case.
+    ///
+    /// Pixels are always in the dimension of the basic info. Frames encoded with
+    /// [`FrameSettings::resampling`] are upsampled to it by libjxl.
     pub fn decode_to_pixels(
         &mut self,
         input_buf: &[u8],
         num_channels: u32,
         sample_format: SampleFormat,
     ) -> Result<Vec<u8>> {
+        self.decode_to_pixels_with_format(input_buf, &PixelFormat::new(num_channels, sample_format))
+    }
+
+    /// Decodes the image to pixels laid out as described by `pixel_format`.
+    pub fn decode_to_pixels_with_format(
+        &mut self,
+        input_buf: &[u8],
+        pixel_format: &PixelFormat,
+    ) -> Result<Vec<u8>> {
+        let decoded = self.decode_pixels_inner(input_buf, pixel_format, &[], 0, false)?;
+        Ok(decoded.color)
+    }
+
+    /// Returns the size of the buffer [`decode_to_pixels_with_format`] would return, without
+    /// decoding any pixels.
+    ///
+    /// Only the input up to the first frame header is read, so this can be used to reject
+    /// oversized images before decoding them.
+    ///
+    /// [`decode_to_pixels_with_format`]: Self::decode_to_pixels_with_format
+    pub fn output_buffer_size(
+        &mut self,
+        input_buf: &[u8],
+        pixel_format: &PixelFormat,
+    ) -> Result<usize> {
         let dec = self.decoder.as_ptr();
+        let pixel_format = pixel_format.to_sys();
 
-        let pixel_format = sys::JxlPixelFormat {
-            num_channels,
-            data_type: match sample_format {
-                SampleFormat::U8 => sys::JxlDataType_JXL_TYPE_UINT8,
-                SampleFormat::U16 => sys::JxlDataType_JXL_TYPE_UINT16,
-                SampleFormat::F16 => sys::JxlDataType_JXL_TYPE_FLOAT16,
-                SampleFormat::F32 => sys::JxlDataType_JXL_TYPE_FLOAT,
-            },
-            endianness: sys::JxlEndianness_JXL_NATIVE_ENDIAN,
-            align: 0,
-        };
+        unsafe {
+            self.reset();
+
+            let ret = sys::JxlDecoderSubscribeEvents(
+                dec,
+                sys::JxlDecoderStatus_JXL_DEC_FULL_IMAGE as i32,
+            );
+            Error::try_from_libjxl_decoder(ret)?;
+
+            // Orientation affects the dimension of the output.
+            let ret = sys::JxlDecoderSetKeepOrientation(dec, sys::JXL_TRUE as i32);
+            Error::try_from_libjxl_decoder(ret)?;
+
+            let ret = sys::JxlDecoderSetInput(dec, input_buf.as_ptr(), input_buf.len());
+            Error::try_from_libjxl_decoder(ret)?;
+
+            let ret = sys::JxlDecoderProcessInput(dec);
+            match ret {
+                sys::JxlDecoderStatus_JXL_DEC_NEED_IMAGE_OUT_BUFFER => {}
+                sys::JxlDecoderStatus_JXL_DEC_ERROR => return Err(Error::BadInput),
+                sys::JxlDecoderStatus_JXL_DEC_NEED_MORE_INPUT => {
+                    return Err(Error::TruncatedInput);
+                }
+                _ => return Err(Error::Libjxl(ret as i32)),
+            }
+
+            let mut basic_info = MaybeUninit::<sys::JxlBasicInfo>::uninit();
+            let ret = sys::JxlDecoderGetBasicInfo(dec, basic_info.as_mut_ptr());
+            Error::try_from_libjxl_decoder(ret)?;
+            let basic_info = basic_info.assume_init();
+            if !is_compatible_num_channels(pixel_format.num_channels, basic_info.num_color_channels)
+            {
+                return Err(Error::ApiUsage);
+            }
+
+            let mut buffer_len = 0usize;
+            let ret = sys::JxlDecoderImageOutBufferSize(dec, &pixel_format, &mut buffer_len);
+            Error::try_from_libjxl_decoder(ret)?;
+
+            sys::JxlDecoderReleaseInput(dec);
+
+            Ok(buffer_len)
+        }
+    }
+
+    /// Decodes the frame at `index`, composited with previous frames as it's displayed.
+    ///
+    /// Frames before it are skipped, which is faster than decoding all frames. Returns
+    /// [`Error::ApiUsage`] if the image has `index` frames or less.
+    pub fn decode_frame(
+        &mut self,
+        input_buf: &[u8],
+        index: u32,
+        num_channels: u32,
+        sample_format: SampleFormat,
+    ) -> Result<Vec<u8>> {
+        let pixel_format = PixelFormat::new(num_channels, sample_format);
+        let decoded = self.decode_pixels_inner(input_buf, &pixel_format, &[], index, false)?;
+        Ok(decoded.color)
+    }
+
+    /// Decodes as much of a possibly truncated image as possible.
+    ///
+    /// If the input ends before the image is fully decoded, pixels decoded so far are returned,
+    /// with [`PartialImage::is_complete`] unset. Image regions which are not decoded yet may be
+    /// blurry or blank, depending on how the image is encoded. Returns
+    /// [`Error::TruncatedInput`] if the input is too short to decode any pixels.
+    pub fn decode_partial(
+        &mut self,
+        input_buf: &[u8],
+        num_channels: u32,
+        sample_format: SampleFormat,
+    ) -> Result<PartialImage> {
+        let pixel_format = PixelFormat::new(num_channels, sample_format);
+        let decoded = self.decode_pixels_inner(input_buf, &pixel_format, &[], 0, true)?;
+        Ok(PartialImage {
+            pixels: decoded.color,
+            is_complete: decoded.complete,
+        })
+    }
+
+    /// Decodes a CMYK image to interleaved CMYK samples.
+    ///
+    /// Samples are in the JPEG XL convention, where 0 means maximum ink. Returns
+    /// [`Error::NotSupported`] if the image doesn't have a black extra channel.
+    pub fn decode_to_cmyk(
+        &mut self,
+        input_buf: &[u8],
+        sample_format: SampleFormat,
+    ) -> Result<Vec<u8>> {
+        let black_channel = self
+            .extra_channel_infos(input_buf)?
+            .iter()
+            .position(|info| info.channel_type() == ExtraChannelType::Black)
+            .ok_or(Error::NotSupported)?;
+
+        let pixel_format = PixelFormat::new(3, sample_format);
+        let DecodedPixels {
+            color: cmy,
+            extra: mut extra_channels,
+            ..
+        } =
+            self.decode_pixels_inner(input_buf, &pixel_format, &[black_channel as u32], 0, false)?;
+        let black = extra_channels.pop().ok_or(Error::Unknown)?;
+
+        let bytes_per_sample = sample_format.bytes_per_sample();
+        let mut cmyk = Vec::with_capacity(cmy.len() + black.len());
+        for (cmy, k) in cmy
+            .chunks_exact(bytes_per_sample * 3)
+            .zip(black.chunks_exact(bytes_per_sample))
+        {
+            cmyk.extend_from_slice(cmy);
+            cmyk.extend_from_slice(k);
+        }
+        Ok(cmyk)
+    }
+
+    /// Decodes color channels, and extra channels with the given indices in separate buffers,
+    /// of the frame after skipping `skip_frames` frames.
+    ///
+    /// If `allow_partial` is set, truncated input is flushed instead of returning an error.
+    fn decode_pixels_inner(
+        &mut self,
+        input_buf: &[u8],
+        pixel_format: &PixelFormat,
+        extra_channels: &[u32],
+        skip_frames: u32,
+        allow_partial: bool,
+    ) -> Result<DecodedPixels> {
+        let dec = self.decoder.as_ptr();
+        let pixel_format = pixel_format.to_sys();
 
         unsafe {
-            sys::JxlDecoderReset(dec);
+            self.reset();
 
             let ret = sys::JxlDecoderSubscribeEvents(
                 dec,
@@ -326,12 +1376,40 @@ impl JxlDecoder {
             let ret = sys::JxlDecoderSetKeepOrientation(dec, sys::JXL_TRUE as i32);
             Error::try_from_libjxl_decoder(ret)?;
 
+            let ret = sys::JxlDecoderSetDesiredIntensityTarget(dec, self.desired_intensity_target);
+            Error::try_from_libjxl_decoder(ret)?;
+
+            let ret = sys::JxlDecoderSetRenderSpotcolors(dec, self.render_spot_colors as i32);
+            Error::try_from_libjxl_decoder(ret)?;
+
             let ret = sys::JxlDecoderSetInput(dec, input_buf.as_ptr(), input_buf.len());
             Error::try_from_libjxl_decoder(ret)?;
 
+            if skip_frames > 0 {
+                sys::JxlDecoderSkipFrames(dec, skip_frames as usize);
+            }
+
             let ret = sys::JxlDecoderProcessInput(dec);
-            if ret != sys::JxlDecoderStatus_JXL_DEC_NEED_IMAGE_OUT_BUFFER {
-                return Err(Error::Unknown);
+            match ret {
+                sys::JxlDecoderStatus_JXL_DEC_NEED_IMAGE_OUT_BUFFER => {}
+                sys::JxlDecoderStatus_JXL_DEC_ERROR => return Err(Error::BadInput),
+                sys::JxlDecoderStatus_JXL_DEC_NEED_MORE_INPUT => {
+                    return Err(Error::TruncatedInput);
+                }
+                // All frames are skipped.
+                sys::JxlDecoderStatus_JXL_DEC_SUCCESS if skip_frames > 0 => {
+                    return Err(Error::ApiUsage);
+                }
+                _ => return Err(Error::Libjxl(ret as i32)),
+            }
+
+            let mut basic_info = MaybeUninit::<sys::JxlBasicInfo>::uninit();
+            let ret = sys::JxlDecoderGetBasicInfo(dec, basic_info.as_mut_ptr());
+            Error::try_from_libjxl_decoder(ret)?;
+            let basic_info = basic_info.assume_init();
+            if !is_compatible_num_channels(pixel_format.num_channels, basic_info.num_color_channels)
+            {
+                return Err(Error::ApiUsage);
             }
 
             let mut buffer_len = 0usize;
@@ -347,30 +1425,160 @@ impl JxlDecoder {
             );
             Error::try_from_libjxl_decoder(ret)?;
 
-            loop {
+            let bit_depth = sys::JxlBitDepth::from(self.image_out_bit_depth);
+            let ret = sys::JxlDecoderSetImageOutBitDepth(dec, &bit_depth);
+            if ret != sys::JxlDecoderStatus_JXL_DEC_SUCCESS {
+                return Err(Error::ApiUsage);
+            }
+
+            let mut extra_bufs = Vec::with_capacity(extra_channels.len());
+            for &index in extra_channels {
+                let mut buffer_len = 0usize;
+                let ret = sys::JxlDecoderExtraChannelBufferSize(
+                    dec,
+                    &pixel_format,
+                    &mut buffer_len,
+                    index,
+                );
+                Error::try_from_libjxl_decoder(ret)?;
+
+                // Moving the Vec into `extra_bufs` doesn't move its heap buffer.
+                let mut extra_buf = vec![0u8; buffer_len];
+                let ret = sys::JxlDecoderSetExtraChannelBuffer(
+                    dec,
+                    &pixel_format,
+                    extra_buf.as_mut_ptr().cast(),
+                    buffer_len,
+                    index,
+                );
+                Error::try_from_libjxl_decoder(ret)?;
+                extra_bufs.push(extra_buf);
+            }
+
+            let complete = loop {
                 let ret = sys::JxlDecoderProcessInput(dec);
                 match ret {
-                    sys::JxlDecoderStatus_JXL_DEC_FULL_IMAGE => break,
-                    sys::JxlDecoderStatus_JXL_DEC_SUCCESS
-                    | sys::JxlDecoderStatus_JXL_DEC_ERROR
-                    | sys::JxlDecoderStatus_JXL_DEC_NEED_MORE_INPUT => {
-                        return Err(Error::Unknown);
+                    sys::JxlDecoderStatus_JXL_DEC_FULL_IMAGE => break true,
+                    sys::JxlDecoderStatus_JXL_DEC_NEED_MORE_INPUT if allow_partial => {
+                        let ret = sys::JxlDecoderFlushImage(dec);
+                        if ret != sys::JxlDecoderStatus_JXL_DEC_SUCCESS {
+                            return Err(Error::TruncatedInput);
+                        }
+                        break false;
                     }
+                    sys::JxlDecoderStatus_JXL_DEC_NEED_MORE_INPUT => {
+                        return Err(Error::TruncatedInput);
+                    }
+                    sys::JxlDecoderStatus_JXL_DEC_ERROR => return Err(Error::BadInput),
+                    sys::JxlDecoderStatus_JXL_DEC_SUCCESS => return Err(Error::Libjxl(ret as i32)),
                     _ => {}
                 }
-            }
+            };
 
             sys::JxlDecoderReleaseInput(dec);
 
-            Ok(out_buf)
+            Ok(DecodedPixels {
+                color: out_buf,
+                extra: extra_bufs,
+                complete,
+            })
+        }
+    }
+
+    /// Decodes the image to pixels, and returns an iterator over its scanlines.
+    ///
+    /// Each row is `xsize * num_channels * bytes_per_sample` bytes long, in the same layout as
+    /// [`decode_to_pixels`][Self::decode_to_pixels].
+    pub fn decode_rows(
+        &mut self,
+        input_buf: &[u8],
+        num_channels: u32,
+        sample_format: SampleFormat,
+    ) -> Result<RowIter<'_>> {
+        let basic_info = self.basic_info(input_buf)?;
+        self.rows = self.decode_to_pixels(input_buf, num_channels, sample_format)?;
+
+        let stride =
+            basic_info.xsize as usize * num_channels as usize * sample_format.bytes_per_sample();
+        let expected = stride * basic_info.ysize as usize;
+        if self.rows.len() != expected {
+            return Err(Error::BufferSizeMismatch {
+                expected,
+                got: self.rows.len(),
+            });
         }
+        Ok(RowIter {
+            rows: self.rows.chunks_exact(stride),
+        })
     }
 
+    /// Returns whether the image has JPEG reconstruction data, so that it can be decoded with
+    /// [`decode_to_jpeg`][Self::decode_to_jpeg].
+    ///
+    /// Only the input up to the first frame header is read.
+    pub fn has_jpeg_reconstruction(&mut self, input_buf: &[u8]) -> Result<bool> {
+        let dec = self.decoder.as_ptr();
+
+        unsafe {
+            self.reset();
+
+            let ret = sys::JxlDecoderSubscribeEvents(
+                dec,
+                (sys::JxlDecoderStatus_JXL_DEC_JPEG_RECONSTRUCTION
+                    | sys::JxlDecoderStatus_JXL_DEC_FRAME) as i32,
+            );
+            Error::try_from_libjxl_decoder(ret)?;
+
+            let ret = sys::JxlDecoderSetInput(dec, input_buf.as_ptr(), input_buf.len());
+            Error::try_from_libjxl_decoder(ret)?;
+
+            let ret = sys::JxlDecoderProcessInput(dec);
+            let has_jpeg_reconstruction = match ret {
+                sys::JxlDecoderStatus_JXL_DEC_JPEG_RECONSTRUCTION => true,
+                // Reconstruction data comes before the codestream.
+                sys::JxlDecoderStatus_JXL_DEC_FRAME | sys::JxlDecoderStatus_JXL_DEC_SUCCESS => {
+                    false
+                }
+                sys::JxlDecoderStatus_JXL_DEC_ERROR => return Err(Error::BadInput),
+                sys::JxlDecoderStatus_JXL_DEC_NEED_MORE_INPUT => {
+                    return Err(Error::TruncatedInput);
+                }
+                _ => return Err(Error::Libjxl(ret as i32)),
+            };
+
+            sys::JxlDecoderReleaseInput(dec);
+
+            Ok(has_jpeg_reconstruction)
+        }
+    }
+
+    /// Reconstructs the original JPEG bitstream.
+    ///
+    /// The output buffer starts at a size estimated from the input; use
+    /// [`decode_to_jpeg_with_capacity`][Self::decode_to_jpeg_with_capacity] if the size of the
+    /// JPEG is known in advance.
     pub fn decode_to_jpeg(&mut self, input_buf: &[u8]) -> Result<Vec<u8>> {
+        // Lossless JPEG recompression saves about 20% of the size, so the reconstructed JPEG is
+        // usually slightly larger than the input.
+        let size_hint = input_buf.len() + input_buf.len() / 4;
+        self.decode_to_jpeg_with_capacity(input_buf, size_hint)
+    }
+
+    /// Reconstructs the original JPEG bitstream into a buffer with initial capacity of
+    /// `capacity` bytes.
+    ///
+    /// The buffer doubles in size each time libjxl runs out of space.
+    pub fn decode_to_jpeg_with_capacity(
+        &mut self,
+        input_buf: &[u8],
+        capacity: usize,
+    ) -> Result<Vec<u8>> {
+        const MIN_CAPACITY: usize = 1 << 16;
+
         let dec = self.decoder.as_ptr();
 
         unsafe {
-            sys::JxlDecoderReset(dec);
+            self.reset();
 
             let ret = sys::JxlDecoderSubscribeEvents(
                 dec,
@@ -383,12 +1591,19 @@ impl JxlDecoder {
             Error::try_from_libjxl_decoder(ret)?;
 
             let ret = sys::JxlDecoderProcessInput(dec);
-            if ret != sys::JxlDecoderStatus_JXL_DEC_JPEG_RECONSTRUCTION {
-                tracing::debug!(?ret);
-                return Err(Error::Unknown);
+            match ret {
+                sys::JxlDecoderStatus_JXL_DEC_JPEG_RECONSTRUCTION => {}
+                sys::JxlDecoderStatus_JXL_DEC_ERROR => return Err(Error::BadInput),
+                sys::JxlDecoderStatus_JXL_DEC_NEED_MORE_INPUT => {
+                    return Err(Error::TruncatedInput);
+                }
+                _ => {
+                    tracing::debug!(?ret, "JPEG reconstruction event not found");
+                    return Err(Error::NotJpegReconstructible);
+                }
             }
 
-            let mut output = Vec::<u8>::with_capacity(1 << 20);
+            let mut output = Vec::<u8>::with_capacity(capacity.max(MIN_CAPACITY));
             let ret =
                 sys::JxlDecoderSetJPEGBuffer(dec, output.as_mut_ptr().cast(), output.capacity());
             Error::try_from_libjxl_decoder(ret)?;
@@ -397,16 +1612,16 @@ impl JxlDecoder {
                 let ret = sys::JxlDecoderProcessInput(dec);
                 match ret {
                     sys::JxlDecoderStatus_JXL_DEC_FULL_IMAGE => break,
-                    sys::JxlDecoderStatus_JXL_DEC_SUCCESS
-                    | sys::JxlDecoderStatus_JXL_DEC_ERROR
-                    | sys::JxlDecoderStatus_JXL_DEC_NEED_MORE_INPUT => {
-                        return Err(Error::Unknown);
+                    sys::JxlDecoderStatus_JXL_DEC_SUCCESS => return Err(Error::Libjxl(ret as i32)),
+                    sys::JxlDecoderStatus_JXL_DEC_ERROR => return Err(Error::BadInput),
+                    sys::JxlDecoderStatus_JXL_DEC_NEED_MORE_INPUT => {
+                        return Err(Error::TruncatedInput);
                     }
                     sys::JxlDecoderStatus_JXL_DEC_JPEG_NEED_MORE_OUTPUT => {
                         let bytes_unused = sys::JxlDecoderReleaseJPEGBuffer(dec);
                         let output_ptr = output.capacity() - bytes_unused;
                         output.set_len(output_ptr);
-                        output.reserve(bytes_unused + (1 << 20));
+                        output.reserve(output.capacity());
 
                         let uninit = output.spare_capacity_mut();
                         let ret = sys::JxlDecoderSetJPEGBuffer(
@@ -429,6 +1644,140 @@ impl JxlDecoder {
             Ok(output)
         }
     }
+
+    /// Reads all metadata boxes in the container, in the order of appearance.
+    ///
+    /// Signature, codestream and JPEG reconstruction boxes are skipped. Brotli-compressed boxes
+    /// are decompressed, and marked as compressed so that they're compressed again when added to
+    /// an encoder.
+    pub fn boxes(&mut self, input_buf: &[u8]) -> Result<Vec<MetadataBox>> {
+        self.boxes_inner(input_buf, true)
+    }
+
+    /// Reads all metadata boxes in the container as stored, without decompressing them.
+    ///
+    /// Brotli-compressed boxes are returned as `brob` boxes, whose contents are the type of the
+    /// compressed box followed by the compressed data. Adding them to an encoder writes them
+    /// back byte for byte.
+    pub fn raw_boxes(&mut self, input_buf: &[u8]) -> Result<Vec<MetadataBox>> {
+        self.boxes_inner(input_buf, false)
+    }
+
+    fn boxes_inner(&mut self, input_buf: &[u8], decompress: bool) -> Result<Vec<MetadataBox>> {
+        let dec = self.decoder.as_ptr();
+
+        unsafe {
+            self.reset();
+
+            let ret = sys::JxlDecoderSubscribeEvents(
+                dec,
+                (sys::JxlDecoderStatus_JXL_DEC_BOX | sys::JxlDecoderStatus_JXL_DEC_BOX_COMPLETE)
+                    as i32,
+            );
+            Error::try_from_libjxl_decoder(ret)?;
+
+            let ret = sys::JxlDecoderSetDecompressBoxes(dec, decompress as i32);
+            Error::try_from_libjxl_decoder(ret)?;
+
+            let ret = sys::JxlDecoderSetInput(dec, input_buf.as_ptr(), input_buf.len());
+            Error::try_from_libjxl_decoder(ret)?;
+            sys::JxlDecoderCloseInput(dec);
+
+            let mut boxes = Vec::new();
+            let mut current = None::<MetadataBox>;
+            loop {
+                let ret = sys::JxlDecoderProcessInput(dec);
+                match ret {
+                    sys::JxlDecoderStatus_JXL_DEC_SUCCESS => break,
+                    sys::JxlDecoderStatus_JXL_DEC_ERROR => return Err(Error::BadInput),
+                    sys::JxlDecoderStatus_JXL_DEC_NEED_MORE_INPUT => {
+                        return Err(Error::TruncatedInput);
+                    }
+                    sys::JxlDecoderStatus_JXL_DEC_BOX => {
+                        let mut raw_type = [0 as std::ffi::c_char; 4];
+                        let ret = sys::JxlDecoderGetBoxType(
+                            dec,
+                            raw_type.as_mut_ptr(),
+                            sys::JXL_FALSE as i32,
+                        );
+                        Error::try_from_libjxl_decoder(ret)?;
+                        let mut box_type = [0 as std::ffi::c_char; 4];
+                        let ret = sys::JxlDecoderGetBoxType(
+                            dec,
+                            box_type.as_mut_ptr(),
+                            sys::JXL_TRUE as i32,
+                        );
+                        Error::try_from_libjxl_decoder(ret)?;
+
+                        let raw_type = raw_type.map(|c| c as u8);
+                        let box_type = box_type.map(|c| c as u8);
+                        if MetadataBox::is_reserved_type(box_type) {
+                            continue;
+                        }
+
+                        let mut size = 0u64;
+                        let ret = sys::JxlDecoderGetBoxSizeContents(dec, &mut size);
+                        Error::try_from_libjxl_decoder(ret)?;
+
+                        // Box size is untrusted, and the buffer grows if it's too small.
+                        let capacity = size.min(input_buf.len() as u64).max(64) as usize;
+                        let mut metadata_box = if decompress {
+                            let mut metadata_box =
+                                MetadataBox::new(box_type, Vec::with_capacity(capacity));
+                            metadata_box.set_compressed(&raw_type == b"brob");
+                            metadata_box
+                        } else {
+                            MetadataBox::new(raw_type, Vec::with_capacity(capacity))
+                        };
+
+                        let output = &mut metadata_box.contents;
+                        let ret = sys::JxlDecoderSetBoxBuffer(
+                            dec,
+                            output.as_mut_ptr(),
+                            output.capacity(),
+                        );
+                        Error::try_from_libjxl_decoder(ret)?;
+                        current = Some(metadata_box);
+                    }
+                    sys::JxlDecoderStatus_JXL_DEC_BOX_NEED_MORE_OUTPUT => {
+                        let Some(metadata_box) = &mut current else {
+                            return Err(Error::Unknown);
+                        };
+                        let output = &mut metadata_box.contents;
+
+                        let bytes_unused = sys::JxlDecoderReleaseBoxBuffer(dec);
+                        let output_ptr = output.capacity() - bytes_unused;
+                        output.set_len(output_ptr);
+                        output.reserve(bytes_unused + output.capacity());
+
+                        let uninit = output.spare_capacity_mut();
+                        let ret = sys::JxlDecoderSetBoxBuffer(
+                            dec,
+                            uninit.as_mut_ptr().cast(),
+                            uninit.len(),
+                        );
+                        Error::try_from_libjxl_decoder(ret)?;
+                    }
+                    sys::JxlDecoderStatus_JXL_DEC_BOX_COMPLETE => {
+                        let Some(mut metadata_box) = current.take() else {
+                            return Err(Error::Unknown);
+                        };
+                        let output = &mut metadata_box.contents;
+
+                        let bytes_unused = sys::JxlDecoderReleaseBoxBuffer(dec);
+                        let bytes_written = output.capacity() - bytes_unused;
+                        output.set_len(bytes_written);
+                        boxes.push(metadata_box);
+                    }
+                    _ => {}
+                }
+            }
+
+            sys::JxlDecoderReleaseInput(dec);
+
+            Ok(boxes)
+        }
+    }
 }
 
 impl Drop for JxlDecoder {
@@ -438,3 +1787,50 @@ impl Drop for JxlDecoder {
         }
     }
 }
+
+// SAFETY: libjxl decoder instances aren't tied to the thread which created them, as long as
+// they aren't used concurrently. The parallel runners don't have thread-local state either.
+unsafe impl Send for JxlDecoder {}
+
+/// Pixels decoded from a possibly truncated image.
+#[derive(Debug, Clone)]
+pub struct PartialImage {
+    pub pixels: Vec<u8>,
+    /// Whether the whole image is decoded.
+    pub is_complete: bool,
+}
+
+struct DecodedPixels {
+    color: Vec<u8>,
+    extra: Vec<Vec<u8>>,
+    complete: bool,
+}
+
+/// Iterator over scanlines returned by [`JxlDecoder::decode_rows`].
+#[derive(Debug, Clone)]
+pub struct RowIter<'decoder> {
+    rows: std::slice::ChunksExact<'decoder, u8>,
+}
+
+impl<'decoder> Iterator for RowIter<'decoder> {
+    type Item = &'decoder [u8];
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.rows.next()
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.rows.size_hint()
+    }
+}
+
+impl DoubleEndedIterator for RowIter<'_> {
+    #[inline]
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.rows.next_back()
+    }
+}
+
+impl ExactSizeIterator for RowIter<'_> {}
@@ -1,3 +1,4 @@
+use std::borrow::Cow;
 use std::fs::File;
 use std::io::ErrorKind;
 use std::io::IsTerminal;
@@ -5,24 +6,43 @@ use std::io::prelude::*;
 use std::path::{Path, PathBuf};
 use std::sync::atomic::AtomicUsize;
 use std::sync::atomic::Ordering;
+use std::sync::{Condvar, Mutex};
 use std::time::{Duration, Instant};
 
 use clap::Parser;
 use crossterm::ExecutableCommand;
 use eyre::{Context, OptionExt};
-use image::ImageDecoder;
+use image::{AnimationDecoder, ImageDecoder};
 use indicatif::{ProgressState, ProgressStyle};
-use rayon::prelude::*;
 use tracing_indicatif::span_ext::IndicatifSpanExt;
 
 #[derive(Debug, Parser)]
-#[command(version)]
+#[command(
+    version,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
     /// Encoding distance. Value of 0 triggers lossless encoding.
     ///
     /// Corresponds to cjxl `-d`.
     #[arg(short, long)]
     distance: Option<f32>,
+    /// Search for the distance which encodes the image in at most the given size.
+    ///
+    /// The image is encoded repeatedly until the size is within 5% of the target.
+    #[arg(long, value_name = "BYTES", conflicts_with_all = ["distance", "target_bpp"])]
+    target_size: Option<u64>,
+    /// Search for the distance which encodes the image in at most the given bits per pixel.
+    #[arg(long, value_name = "BPP", conflicts_with = "distance")]
+    target_bpp: Option<f64>,
+    /// Encode the image both losslessly and lossily, and keep whichever is smaller.
+    ///
+    /// The lossy encode uses `--distance` if it's positive, or distance 1 otherwise.
+    #[arg(long, conflicts_with_all = ["target_size", "target_bpp"])]
+    auto: bool,
     /// Encoding effort.
     ///
     /// Corresponds to cjxl `-e`.
@@ -43,7 +63,7 @@ struct Args {
     /// This will encode lossy Modular image when used with positive distance settings.
     #[arg(short = 'm', long)]
     force_modular: bool,
-    /// Output file name.
+    /// Output file name, or `-` to write to standard output.
     ///
     /// If not given, it will write nothing and work like cjxl `--disable_output`.
     #[arg(short, long)]
@@ -51,29 +71,251 @@ struct Args {
     /// Whether to disable lossless JPEG transcoding and force encoding from pixels.
     #[arg(long)]
     force_from_pixels: bool,
+    /// Rotate and flip pixels according to the Exif orientation, instead of storing the
+    /// orientation in the image header.
+    ///
+    /// JPEG input with non-identity orientation is encoded from pixels, as lossless transcoding
+    /// keeps pixels as-is.
+    #[arg(long, overrides_with = "keep_orientation")]
+    apply_orientation: bool,
+    /// Store the Exif orientation in the image header, keeping pixels as-is. This is the default.
+    #[arg(long, overrides_with = "apply_orientation")]
+    keep_orientation: bool,
+    /// Reduce 16-bit input to 8 bits with ordered dithering when encoding lossy image.
+    ///
+    /// This can reduce banding in smooth gradients at low bitrates.
+    #[arg(long)]
+    dither: bool,
+    /// Encode alpha channel losslessly, even if color channels are lossy.
+    #[arg(long)]
+    lossless_alpha: bool,
+    /// Treat color channels of the input as premultiplied by alpha.
+    #[arg(long)]
+    premultiplied_alpha: bool,
     #[arg(short, long)]
     recursive: bool,
+    /// Comma-separated list of file extensions to encode in recursive mode.
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_value = "png,jpg,jpeg,webp,gif",
+        requires = "recursive"
+    )]
+    extensions: Vec<String>,
+    /// Follow symbolic links in recursive mode.
+    ///
+    /// Links forming a loop are reported as errors and skipped.
+    #[arg(long, requires = "recursive")]
+    follow_symlinks: bool,
+    /// Skip images whose output file is not older than the input file in recursive mode.
+    ///
+    /// Outputs older than the input are encoded again if `--overwrite` is also given.
+    #[arg(long, requires = "recursive")]
+    skip_existing: bool,
+    /// Number of threads used for encoding, including images encoded concurrently in recursive
+    /// mode. Defaults to the number of CPUs.
+    #[arg(short, long, value_parser = clap::value_parser!(u32).range(1..))]
+    jobs: Option<u32>,
+    /// Limit estimated memory usage of images being encoded concurrently in recursive mode.
+    ///
+    /// An image larger than the limit is still encoded, but only when no other image is being
+    /// encoded.
+    #[arg(long, value_name = "BYTES")]
+    max_memory: Option<u64>,
     #[arg(short = 'f', long)]
     overwrite: bool,
+    /// Size of the buffer encoded output is written to at once.
+    #[arg(
+        long,
+        value_name = "BYTES",
+        default_value_t = 1024 * 1024,
+        value_parser = clap::value_parser!(u64).range(32..)
+    )]
+    output_buffer_size: u64,
+    /// Copy access and modification times of the input file to the output file.
+    #[arg(long)]
+    preserve_timestamps: bool,
     #[arg(long)]
     verify: bool,
-    /// Input file name.
+    /// Verify lossy encodes by checking the maximum error of decoded samples of each channel,
+    /// normalized to `0..=1`, against the given value.
+    ///
+    /// Lossless and transcoded images are still verified to be exact. Implies `--verify`.
+    #[arg(long, value_name = "MAX_ERROR")]
+    verify_distance: Option<f32>,
+    /// Compare the encoded image with an existing JPEG XL image of the same source.
+    ///
+    /// Reports the size difference and PSNR between the two images.
+    #[arg(long, value_name = "FILE", conflicts_with = "recursive")]
+    compare: Option<PathBuf>,
+    /// Print container and codestream structure of the image.
+    ///
+    /// JPEG XL input is inspected without encoding. Otherwise the encoded image is inspected.
+    #[arg(long, conflicts_with = "recursive")]
+    inspect: bool,
+    /// Print encoding statistics of each image as a line of JSON to stdout.
+    #[arg(long, conflicts_with = "inspect")]
+    stats_json: bool,
+    /// Embed the input file name in a `fnam` metadata box.
+    #[arg(long)]
+    embed_filename: bool,
+    /// Encode a generated test pattern instead of reading an input file.
+    ///
+    /// Available patterns are `solid`, `gradient`, `checker` and `noise`.
+    #[arg(
+        long,
+        value_name = "PATTERN:WxH",
+        conflicts_with_all = ["input", "recursive"],
+    )]
+    generate: Option<GeneratedImage>,
+    /// Input file name, or `-` to read from standard input.
+    #[arg(required_unless_present = "generate")]
+    input: Option<PathBuf>,
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum Command {
+    /// Decode a JPEG XL image to PNG or JPEG, like djxl.
+    Decode(DecodeArgs),
+}
+
+#[derive(Debug, clap::Args)]
+struct DecodeArgs {
+    /// Input file name, or `-` to read from standard input.
     input: PathBuf,
+    /// Output file name, or `-` to write to standard output.
+    ///
+    /// Output format is guessed from the extension. JPEG output reconstructs the original JPEG
+    /// if possible, and falls back to encoding decoded pixels. Standard output gets the
+    /// reconstructed JPEG if possible, and PNG otherwise.
+    #[arg(short, long)]
+    output: PathBuf,
+    /// Keep pixels as stored, without applying the orientation in the image header.
+    #[arg(long)]
+    keep_orientation: bool,
+    #[arg(short = 'f', long)]
+    overwrite: bool,
 }
 
 #[derive(Debug)]
 struct EncodingStats {
-    input_format: image::ImageFormat,
+    /// `None` if the image is generated.
+    input_format: Option<image::ImageFormat>,
     image_dimension: (u32, u32),
     bits_per_sample: u32,
     is_lossless: bool,
     is_transcoded: bool,
+    num_frames: usize,
+    /// Distance used to encode pixels, or `None` if transcoded.
+    distance: Option<f32>,
     input_size: u64,
     output_size: u64,
     duration_read_image: Duration,
     duration_decode_image: Duration,
     duration_encode: Duration,
     duration_output: Duration,
+    comparison: Option<Comparison>,
+    /// Sizes of the candidates if the encoding mode is chosen with `--auto`.
+    auto_choice: Option<AutoChoice>,
+    /// Encoded image, kept if it should be inspected.
+    encoded: Option<Vec<u8>>,
+}
+
+#[derive(Debug)]
+struct Comparison {
+    reference_size: u64,
+    psnr: f64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct AutoChoice {
+    lossless_size: u64,
+    lossy_size: u64,
+    lossy_distance: f32,
+}
+
+impl EncodingStats {
+    /// Serializes statistics as a single-line JSON object.
+    fn to_json(&self, input_path: Option<&Path>) -> String {
+        fn string(s: &str) -> String {
+            let mut out = String::with_capacity(s.len() + 2);
+            out.push('"');
+            for c in s.chars() {
+                match c {
+                    '"' => out.push_str("\\\""),
+                    '\\' => out.push_str("\\\\"),
+                    '\n' => out.push_str("\\n"),
+                    '\r' => out.push_str("\\r"),
+                    '\t' => out.push_str("\\t"),
+                    c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+                    c => out.push(c),
+                }
+            }
+            out.push('"');
+            out
+        }
+
+        fn number(x: f64) -> String {
+            if x.is_finite() {
+                x.to_string()
+            } else {
+                String::from("null")
+            }
+        }
+
+        fn millis(duration: Duration) -> String {
+            number(duration.as_secs_f64() * 1000.)
+        }
+
+        let (width, height) = self.image_dimension;
+        let mut fields = vec![
+            (
+                "input",
+                input_path.map_or_else(
+                    || String::from("null"),
+                    |path| string(&path.to_string_lossy()),
+                ),
+            ),
+            (
+                "format",
+                self.input_format.map_or_else(
+                    || String::from("null"),
+                    |format| string(&format!("{format:?}")),
+                ),
+            ),
+            ("width", width.to_string()),
+            ("height", height.to_string()),
+            ("bits_per_sample", self.bits_per_sample.to_string()),
+            ("lossless", self.is_lossless.to_string()),
+            ("transcoded", self.is_transcoded.to_string()),
+            ("frames", self.num_frames.to_string()),
+            (
+                "distance",
+                self.distance
+                    .map_or_else(|| String::from("null"), |d| number(d as f64)),
+            ),
+            ("input_size", self.input_size.to_string()),
+            ("output_size", self.output_size.to_string()),
+            ("read_ms", millis(self.duration_read_image)),
+            ("decode_ms", millis(self.duration_decode_image)),
+            ("encode_ms", millis(self.duration_encode)),
+            ("output_ms", millis(self.duration_output)),
+        ];
+        if let Some(comparison) = &self.comparison {
+            fields.push(("reference_size", comparison.reference_size.to_string()));
+            fields.push(("psnr", number(comparison.psnr)));
+        }
+        if let Some(choice) = &self.auto_choice {
+            fields.push(("auto_lossless_size", choice.lossless_size.to_string()));
+            fields.push(("auto_lossy_size", choice.lossy_size.to_string()));
+        }
+
+        let fields = fields
+            .into_iter()
+            .map(|(key, value)| format!("\"{key}\":{value}"))
+            .collect::<Vec<_>>();
+        format!("{{{}}}", fields.join(","))
+    }
 }
 
 fn init_subscriber(_args: &Args) {
@@ -111,18 +353,70 @@ fn init_subscriber(_args: &Args) {
 fn main() {
     let args = Args::parse();
     init_subscriber(&args);
+    tracing::debug!("Using libjxl {}", jexcel::version_string());
+
+    if let Some(Command::Decode(decode_args)) = &args.command {
+        if let Err(err) = decode_single(decode_args) {
+            tracing::error!(%err, "Error decoding image \"{}\"", decode_args.input.display());
+        }
+        return;
+    }
+
+    if let Some(jobs) = args.jobs
+        && let Err(err) = rayon::ThreadPoolBuilder::new()
+            .num_threads(jobs as usize)
+            .build_global()
+    {
+        tracing::error!(%err, "Error creating thread pool");
+        return;
+    }
+
+    let output_is_stdout = args.output.as_deref().is_some_and(is_stdio);
+    if output_is_stdout {
+        if args.recursive {
+            tracing::error!("Cannot write to standard output in recursive mode");
+            return;
+        }
+        if args.inspect || args.stats_json {
+            tracing::error!("Cannot print to standard output while writing image to it");
+            return;
+        }
+        if std::io::stdout().is_terminal() {
+            tracing::error!("Refusing to write image to terminal");
+            return;
+        }
+    }
 
     if args.recursive {
-        let span = tracing::info_span!("collect files", input = %args.input.display());
+        let input_root = args
+            .input
+            .as_deref()
+            .expect("input is required in recursive mode");
+        if is_stdio(input_root) {
+            tracing::error!("Cannot read from standard input in recursive mode");
+            return;
+        }
+        let span = tracing::info_span!("collect files", input = %input_root.display());
         span.pb_set_message("Collecting input files");
 
-        let files = span.in_scope(|| {
-            let glob = globset::GlobSet::builder()
-                .add(globset::Glob::new("**/*.{png,jpg,jpeg,webp}").unwrap())
-                .build()
-                .expect("failed to compile globset");
+        let mut glob = globset::GlobSet::builder();
+        for ext in &args.extensions {
+            let ext = ext.trim().trim_start_matches('.');
+            match globset::Glob::new(&format!("**/*.{ext}")) {
+                Ok(x) => {
+                    glob.add(x);
+                }
+                Err(err) => {
+                    tracing::error!(%err, "Invalid extension \"{ext}\"");
+                    return;
+                }
+            }
+        }
+        let glob = glob.build().expect("failed to compile globset");
 
-            let files = walkdir::WalkDir::new(&args.input)
+        let files = span.in_scope(|| {
+            let files = walkdir::WalkDir::new(input_root)
+                .follow_links(args.follow_symlinks)
                 .into_iter()
                 .filter_map(|entry| {
                     entry
@@ -147,7 +441,7 @@ fn main() {
                 })
                 .filter(|path| {
                     let relpath = path
-                        .strip_prefix(&args.input)
+                        .strip_prefix(input_root)
                         .expect("cannot strip prefix from input path");
                     glob.is_match(relpath)
                 })
@@ -168,13 +462,15 @@ fn main() {
         parent_span.pb_set_length(files.len() as u64);
         let _guard = parent_span.enter();
 
+        let memory_budget = args.max_memory.map(MemoryBudget::new);
         let num_success = AtomicUsize::new(0);
         let num_transcoded = AtomicUsize::new(0);
-        files.into_par_iter().for_each(|path| {
+        let num_skipped = AtomicUsize::new(0);
+        let encode_file = |path: &PathBuf| {
             let _guard = parent_span.enter();
 
             let relpath = path
-                .strip_prefix(&args.input)
+                .strip_prefix(input_root)
                 .expect("cannot strip prefix from input path");
 
             let output_path = args
@@ -182,16 +478,23 @@ fn main() {
                 .as_ref()
                 .map(|path| path.join(relpath).with_extension("jxl"));
 
-            if let Some(path) = &output_path {
-                if let Some(parent) = path.parent() {
-                    if let Err(err) = std::fs::create_dir_all(parent) {
-                        tracing::error!(%err, "Error creating directories for \"{}\"", relpath.display());
-                        parent_span.pb_inc(1);
-                        return;
-                    }
+            if let Some(output) = &output_path {
+                if let Some(parent) = output.parent()
+                    && let Err(err) = std::fs::create_dir_all(parent)
+                {
+                    tracing::error!(%err, "Error creating directories for \"{}\"", relpath.display());
+                    parent_span.pb_inc(1);
+                    return;
                 }
 
-                if let Err(err) = ensure_file_inexist(path, args.overwrite) {
+                if args.skip_existing && is_up_to_date(path, output) {
+                    tracing::debug!("Skipping \"{}\"; output is up to date", relpath.display());
+                    num_skipped.fetch_add(1, Ordering::Relaxed);
+                    parent_span.pb_inc(1);
+                    return;
+                }
+
+                if let Err(err) = ensure_file_inexist(output, args.overwrite) {
                     tracing::error!(%err, "Error checking path \"{}\"", relpath.display());
                     parent_span.pb_inc(1);
                     return;
@@ -205,7 +508,18 @@ fn main() {
             span.pb_set_message(&format!("Encoding {}", relpath.display()));
             let _guard = span.entered();
 
-            let stats = match encode_single(&path, output_path, &args) {
+            // Decoders of the `image` crate may panic on malformed input, which shouldn't abort
+            // the whole batch.
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                encode_single(
+                    InputSource::File(path),
+                    output_path,
+                    &args,
+                    memory_budget.as_ref(),
+                )
+            }))
+            .unwrap_or_else(|_| Err(eyre::eyre!("panicked while encoding")));
+            let stats = match result {
                 Ok(x) => x,
                 Err(err) => {
                     tracing::error!(%err, "Error encoding image \"{}\"", relpath.display());
@@ -228,31 +542,104 @@ fn main() {
                 (stats.output_size * 8) as f64 / num_pixels as f64,
             );
 
+            if args.stats_json {
+                println!("{}", stats.to_json(Some(relpath)));
+            }
+
             num_success.fetch_add(1, Ordering::Relaxed);
             if stats.is_transcoded {
                 num_transcoded.fetch_add(1, Ordering::Relaxed);
             }
             parent_span.pb_inc(1);
+        };
+
+        // Images are encoded in threads outside of the rayon pool which libjxl tasks run in.
+        // Waiting for the memory budget would otherwise block a rayon worker, which may have
+        // picked up the image while running tasks of another image holding the budget.
+        let next_file = AtomicUsize::new(0);
+        std::thread::scope(|scope| {
+            for _ in 0..rayon::current_num_threads().min(num_files) {
+                scope.spawn(|| {
+                    while let Some(path) = files.get(next_file.fetch_add(1, Ordering::Relaxed)) {
+                        encode_file(path);
+                    }
+                });
+            }
         });
 
         let num_success = num_success.into_inner();
         let num_transcoded = num_transcoded.into_inner();
-        let num_failure = num_files - num_success;
+        let num_skipped = num_skipped.into_inner();
+        let num_failure = num_files - num_success - num_skipped;
         tracing::info!(
-            "{num_success} successful ({num_transcoded} losslessly transcoded), {num_failure} failures",
+            "{num_success} successful ({num_transcoded} losslessly transcoded), {num_skipped} skipped, {num_failure} failures",
         );
         if num_failure > 0 {
             tracing::warn!("Recursive encoding had some failures");
         }
     } else {
-        if let Some(path) = &args.output {
-            if let Err(err) = ensure_file_inexist(path, args.overwrite) {
-                tracing::error!(%err, "Error checking path \"{}\"", path.display());
+        // Standard input can be read only once, so it's read upfront.
+        let stdin_buffer = if args.generate.is_none()
+            && let Some(path) = &args.input
+            && is_stdio(path)
+        {
+            match read_input(path) {
+                Ok(x) => Some(x),
+                Err(err) => {
+                    tracing::error!(%err, "Error reading standard input");
+                    return;
+                }
+            }
+        } else {
+            None
+        };
+
+        if args.inspect
+            && args.generate.is_none()
+            && let Some(path) = &args.input
+        {
+            let file_buffer;
+            let input_buffer = if let Some(buffer) = &stdin_buffer {
+                buffer
+            } else {
+                file_buffer = match read_input(path) {
+                    Ok(x) => x,
+                    Err(err) => {
+                        tracing::error!(%err, "Error reading \"{}\"", path.display());
+                        return;
+                    }
+                };
+                &file_buffer
+            };
+
+            if matches!(
+                jexcel::check_signature(input_buffer),
+                jexcel::Signature::Codestream | jexcel::Signature::Container
+            ) {
+                if let Err(err) = inspect_jxl(input_buffer) {
+                    tracing::error!(%err, "Error inspecting image \"{}\"", path.display());
+                }
                 return;
             }
         }
 
-        let stats = match encode_single(&args.input, args.output.as_ref(), &args) {
+        if let Some(path) = &args.output
+            && !output_is_stdout
+            && let Err(err) = ensure_file_inexist(path, args.overwrite)
+        {
+            tracing::error!(%err, "Error checking path \"{}\"", path.display());
+            return;
+        }
+
+        let input = match (&args.generate, &args.input) {
+            (Some(generated), _) => InputSource::Generated(generated),
+            (None, Some(path)) => match &stdin_buffer {
+                Some(buffer) => InputSource::Stdin(buffer),
+                None => InputSource::File(path),
+            },
+            (None, None) => unreachable!("input is required unless generating an image"),
+        };
+        let stats = match encode_single(input, args.output.as_ref(), &args, None) {
             Ok(x) => x,
             Err(err) => {
                 if let Some(path) = &args.output {
@@ -264,15 +651,29 @@ fn main() {
             }
         };
 
+        if args.stats_json {
+            let input_path = args.input.as_deref().filter(|_| args.generate.is_none());
+            println!("{}", stats.to_json(input_path));
+        }
+
         let (width, height) = stats.image_dimension;
-        tracing::info!(
-            "Input: {:?}, {} x {}, {} bpc, {} bytes",
-            stats.input_format,
-            width,
-            height,
-            stats.bits_per_sample,
-            stats.input_size,
-        );
+        if let Some(format) = stats.input_format {
+            tracing::info!(
+                "Input: {:?}, {} x {}, {} bpc, {} bytes",
+                format,
+                width,
+                height,
+                stats.bits_per_sample,
+                stats.input_size,
+            );
+        } else {
+            tracing::info!(
+                "Input: generated, {} x {}, {} bpc",
+                width,
+                height,
+                stats.bits_per_sample,
+            );
+        }
 
         tracing::info!(
             "{} to {} bytes ({})",
@@ -288,6 +689,29 @@ fn main() {
                 "lossy"
             },
         );
+        if stats.num_frames > 1 {
+            tracing::info!("Encoded {} animation frames", stats.num_frames);
+        }
+
+        if TargetSize::from_args(&args).is_some()
+            && let Some(distance) = stats.distance
+        {
+            tracing::info!("Chose distance {distance:.3} for target size");
+        }
+
+        if let Some(choice) = &stats.auto_choice {
+            tracing::info!(
+                "Chose {} encoding (lossless {} bytes, lossy {} bytes at distance {:.3})",
+                if stats.is_lossless {
+                    "lossless"
+                } else {
+                    "lossy"
+                },
+                choice.lossless_size,
+                choice.lossy_size,
+                choice.lossy_distance,
+            );
+        }
 
         tracing::info!(
             "Reading input took {:.2} ms",
@@ -314,6 +738,73 @@ fn main() {
                 stats.duration_output.as_secs_f64() * 1000.
             );
         }
+
+        if let Some(comparison) = &stats.comparison {
+            let ratio = stats.output_size as f64 / comparison.reference_size as f64;
+            tracing::info!(
+                "{:.1}% {} than reference ({} bytes), PSNR {:.2} dB vs reference",
+                (ratio - 1.).abs() * 100.,
+                if ratio <= 1. { "smaller" } else { "larger" },
+                comparison.reference_size,
+                comparison.psnr,
+            );
+        }
+
+        if let Some(encoded) = &stats.encoded
+            && let Err(err) = inspect_jxl(encoded)
+        {
+            tracing::error!(%err, "Error inspecting encoded image");
+        }
+    }
+}
+
+/// Semaphore limiting estimated memory usage of concurrently encoded images.
+struct MemoryBudget {
+    limit: u64,
+    used: Mutex<u64>,
+    released: Condvar,
+}
+
+impl MemoryBudget {
+    fn new(limit: u64) -> Self {
+        Self {
+            limit,
+            used: Mutex::new(0),
+            released: Condvar::new(),
+        }
+    }
+
+    fn acquire(&self, bytes: u64) -> MemoryGuard<'_> {
+        let mut used = self.used.lock().unwrap();
+        while *used > 0 && *used + bytes > self.limit {
+            used = self.released.wait(used).unwrap();
+        }
+        *used += bytes;
+        MemoryGuard {
+            budget: self,
+            bytes,
+        }
+    }
+}
+
+struct MemoryGuard<'budget> {
+    budget: &'budget MemoryBudget,
+    bytes: u64,
+}
+
+impl Drop for MemoryGuard<'_> {
+    fn drop(&mut self) {
+        *self.budget.used.lock().unwrap() -= self.bytes;
+        self.budget.released.notify_all();
+    }
+}
+
+/// Returns whether `output` exists and is not older than `input`.
+fn is_up_to_date(input: &Path, output: &Path) -> bool {
+    let modified = |path: &Path| std::fs::metadata(path).and_then(|meta| meta.modified());
+    match (modified(input), modified(output)) {
+        (Ok(input), Ok(output)) => output >= input,
+        _ => false,
     }
 }
 
@@ -347,63 +838,680 @@ fn ensure_file_inexist(path: impl AsRef<Path>, overwrite: bool) -> eyre::Result<
     Ok(())
 }
 
+/// Whether the path refers to standard input or output.
+fn is_stdio(path: &Path) -> bool {
+    path == Path::new("-")
+}
+
+/// Reads the whole file, or standard input if the path is `-`.
+fn read_input(path: &Path) -> std::io::Result<Vec<u8>> {
+    if is_stdio(path) {
+        let mut buffer = Vec::new();
+        std::io::stdin().lock().read_to_end(&mut buffer)?;
+        Ok(buffer)
+    } else {
+        std::fs::read(path)
+    }
+}
+
+/// Counts frames of GIF, APNG or WebP images from their container structure, without decoding
+/// them.
+///
+/// Returns `None` for other formats, or if the structure is malformed before any frame. The count
+/// is only used for estimating memory usage, so it's not validated further.
+fn count_animation_frames(format: Option<image::ImageFormat>, buffer: &[u8]) -> Option<usize> {
+    match format? {
+        image::ImageFormat::Gif => count_gif_frames(buffer),
+        image::ImageFormat::Png => count_apng_frames(buffer),
+        image::ImageFormat::WebP => count_webp_frames(buffer),
+        _ => None,
+    }
+}
+
+fn count_gif_frames(buffer: &[u8]) -> Option<usize> {
+    // Skips data sub-blocks starting at `pos`, including the block terminator.
+    let skip_sub_blocks = |mut pos: usize| -> Option<usize> {
+        loop {
+            let len = *buffer.get(pos)? as usize;
+            pos += 1 + len;
+            if len == 0 {
+                return Some(pos);
+            }
+        }
+    };
+    let color_table_size = |flags: u8| {
+        if flags & 0x80 != 0 {
+            3usize << ((flags & 7) + 1)
+        } else {
+            0
+        }
+    };
+
+    // Header and logical screen descriptor.
+    let mut pos = 13 + color_table_size(*buffer.get(10)?);
+    let mut num_frames = 0;
+    // Frames decoded from truncated images are still counted.
+    while let Some(&block) = buffer.get(pos) {
+        match block {
+            // Image descriptor, followed by LZW minimum code size and image data.
+            0x2c => {
+                num_frames += 1;
+                pos += 10 + color_table_size(*buffer.get(pos + 9)?);
+                pos = skip_sub_blocks(pos + 1)?;
+            }
+            // Extension label, followed by sub-blocks.
+            0x21 => pos = skip_sub_blocks(pos + 2)?,
+            0x3b => break,
+            _ => return None,
+        }
+    }
+    Some(num_frames)
+}
+
+fn count_apng_frames(buffer: &[u8]) -> Option<usize> {
+    // `acTL` comes before the first `IDAT` in APNG.
+    let mut pos = 8;
+    while let Some(header) = buffer.get(pos..pos + 8) {
+        let len = u32::from_be_bytes(header[..4].try_into().unwrap()) as usize;
+        match &header[4..] {
+            b"acTL" => {
+                let num_frames = buffer.get(pos + 8..pos + 12)?;
+                return Some(u32::from_be_bytes(num_frames.try_into().unwrap()) as usize);
+            }
+            b"IDAT" => break,
+            _ => {}
+        }
+        pos = pos.checked_add(12 + len)?;
+    }
+    Some(1)
+}
+
+fn count_webp_frames(buffer: &[u8]) -> Option<usize> {
+    if buffer.get(..4)? != b"RIFF" || buffer.get(8..12)? != b"WEBP" {
+        return None;
+    }
+
+    let mut pos = 12;
+    let mut num_frames = 0;
+    while let Some(header) = buffer.get(pos..pos + 8) {
+        if &header[..4] == b"ANMF" {
+            num_frames += 1;
+        }
+        // Chunks are padded to even size.
+        let size = u32::from_le_bytes(header[4..].try_into().unwrap()) as usize;
+        pos = pos.checked_add(8 + size + size % 2)?;
+    }
+    Some(num_frames.max(1))
+}
+
+/// Decodes all frames of animated GIF, APNG or WebP images, composited on the canvas.
+///
+/// Frames should be counted with [`count_animation_frames`] first, so that memory can be
+/// reserved for them.
+fn read_animation_frames(
+    format: Option<image::ImageFormat>,
+    buffer: &[u8],
+) -> image::ImageResult<Vec<image::Frame>> {
+    let reader = std::io::Cursor::new(buffer);
+    match format {
+        Some(image::ImageFormat::Gif) => image::codecs::gif::GifDecoder::new(reader)?
+            .into_frames()
+            .collect_frames(),
+        Some(image::ImageFormat::Png) => image::codecs::png::PngDecoder::new(reader)?
+            .apng()?
+            .into_frames()
+            .collect_frames(),
+        Some(image::ImageFormat::WebP) => image::codecs::webp::WebPDecoder::new(reader)?
+            .into_frames()
+            .collect_frames(),
+        _ => Ok(Vec::new()),
+    }
+}
+
+fn copy_timestamps(from: &Path, to: &Path) -> std::io::Result<()> {
+    let metadata = std::fs::metadata(from)?;
+    let mut times = std::fs::FileTimes::new().set_modified(metadata.modified()?);
+    if let Ok(accessed) = metadata.accessed() {
+        times = times.set_accessed(accessed);
+    }
+    File::options().write(true).open(to)?.set_times(times)
+}
+
+#[derive(Debug, Clone, Copy)]
+enum InputSource<'a> {
+    File(&'a Path),
+    /// Contents of standard input, which is read beforehand.
+    Stdin(&'a [u8]),
+    Generated(&'a GeneratedImage),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum TargetSize {
+    Bytes(u64),
+    Bpp(f64),
+}
+
+impl TargetSize {
+    fn from_args(args: &Args) -> Option<Self> {
+        match (args.target_size, args.target_bpp) {
+            (Some(bytes), _) => Some(Self::Bytes(bytes)),
+            (None, Some(bpp)) => Some(Self::Bpp(bpp)),
+            (None, None) => None,
+        }
+    }
+
+    fn bytes(self, (width, height): (u32, u32)) -> u64 {
+        match self {
+            Self::Bytes(bytes) => bytes,
+            Self::Bpp(bpp) => (bpp * width as f64 * height as f64 / 8.) as u64,
+        }
+    }
+}
+
 fn encode_single(
-    input: impl AsRef<Path>,
+    input: InputSource<'_>,
     output_path: Option<impl AsRef<Path>>,
     args: &Args,
+    memory_budget: Option<&MemoryBudget>,
 ) -> eyre::Result<EncodingStats> {
-    let mut distance = args
-        .distance
-        .unwrap_or(if args.force_modular { 0. } else { 1. });
-    let is_lossless = distance < 0.01;
-    let effort = jexcel::Effort::try_from(args.effort).wrap_err("invalid effort settings")?;
-    if is_lossless {
-        distance = 0.;
+    let mut prepared = PreparedInput::new(input, args, memory_budget)?;
+    // Encoder is reset and reused when probing.
+    let mut encoder = jexcel::JxlEncoder::new().ok_or_eyre("failed to create encoder")?;
+
+    let wants_verify = args.verify || args.verify_distance.is_some();
+    if wants_verify && prepared.is_animation() {
+        tracing::warn!("Verifying animations is not supported, skipping verification");
     }
-    let is_modular = is_lossless || args.force_modular;
 
-    let begin_read_image = Instant::now();
-    let input_buffer = std::fs::read(input).wrap_err("failed to read input")?;
-    let input_size = input_buffer.len() as u64;
-    let duration_read_image = begin_read_image.elapsed();
-
-    let image = image::ImageReader::new(std::io::Cursor::new(&input_buffer))
-        .with_guessed_format()
-        .wrap_err("cannot guess image format")?;
-    let format = image.format();
-    let is_jpeg = image.format() == Some(image::ImageFormat::Jpeg);
-    let do_transcode = is_jpeg && !args.force_from_pixels;
-    let mut image = image.into_decoder().wrap_err("failed to parse image")?;
-
-    let mut do_verify = args.verify;
-    if !is_lossless && !do_transcode {
-        do_verify = false;
-    }
-
-    let icc = image.icc_profile().wrap_err("failed to decode image")?;
-    let (width, height) = image.dimensions();
-    let (num_channels, sample_format, has_alpha) = {
-        let color_type = image.color_type();
-        let has_alpha = color_type.has_alpha();
-        let num_channels = color_type.channel_count() as u32;
-        let sample_format = match color_type {
-            image::ColorType::L8
-            | image::ColorType::La8
-            | image::ColorType::Rgb8
-            | image::ColorType::Rgba8 => jexcel::SampleFormat::U8,
-            image::ColorType::L16
-            | image::ColorType::La16
-            | image::ColorType::Rgb16
-            | image::ColorType::Rgba16 => jexcel::SampleFormat::U16,
-            image::ColorType::Rgb32F | image::ColorType::Rgba32F => jexcel::SampleFormat::F32,
-            _ => unimplemented!(),
-        };
-        (num_channels, sample_format, has_alpha)
+    let mut auto_choice = None;
+    let probed = if let Some(target) = TargetSize::from_args(args) {
+        let span = tracing::info_span!("search distance");
+        span.pb_set_message("Searching distance for target size");
+        Some(span.in_scope(|| search_distance(&mut encoder, &mut prepared, args, target))?)
+    } else if args.auto {
+        let span = tracing::info_span!("choose mode");
+        span.pb_set_message("Trying lossless and lossy encoding");
+        let (encoded, choice) = span.in_scope(|| choose_auto(&mut encoder, &mut prepared, args))?;
+        auto_choice = choice;
+        Some(encoded)
+    } else {
+        None
+    };
+
+    let encoded = if let Some(mut encoded) = probed {
+        // Output of the chosen probe is kept in memory.
+        if let Some(mut output) = open_output(output_path.as_ref(), args)? {
+            let begin = Instant::now();
+            output
+                .write_all(encoded.buffer.as_deref().unwrap_or_default())
+                .and_then(|_| output.flush())
+                .wrap_err("failed to write output")?;
+            encoded.duration_output = begin.elapsed();
+        }
+        encoded
+    } else {
+        let mut output = open_output(output_path.as_ref(), args)?;
+        let keep_output = wants_verify || args.compare.is_some() || args.inspect;
+        encode_once(
+            &mut encoder,
+            &mut prepared,
+            args,
+            None,
+            output.as_mut().map(|output| output as &mut dyn Write),
+            keep_output,
+        )?
+    };
+
+    if args.preserve_timestamps
+        && let InputSource::File(input_path) = input
+        && let Some(output_path) = &output_path
+        && !is_stdio(output_path.as_ref())
+    {
+        copy_timestamps(input_path, output_path.as_ref())
+            .wrap_err("failed to preserve timestamps")?;
+    }
+
+    let do_verify = wants_verify
+        && !prepared.is_animation()
+        && (encoded.is_lossless || encoded.is_transcoded || args.verify_distance.is_some());
+    if do_verify && let Some(output_buffer) = &encoded.buffer {
+        let span = tracing::info_span!("verify");
+        span.pb_set_message("Verifying encoded image");
+        let result = span.in_scope(|| {
+            let (dither, encode_sample_format) = prepared.encode_format(encoded.is_lossless, args);
+            let input_buffer = if encoded.is_transcoded {
+                Cow::Borrowed(&*prepared.input_buffer)
+            } else {
+                prepared.encode_pixels(dither)
+            };
+            verify_single(
+                &input_buffer,
+                output_buffer,
+                encoded.is_transcoded,
+                prepared.num_channels,
+                encode_sample_format,
+                args.verify_distance.filter(|_| !encoded.is_lossless),
+            )
+        });
+
+        if let Err(err) = result {
+            tracing::error!(%err, "Encoding verification failed");
+            return Err(err);
+        }
+    }
+
+    let comparison = if let Some(reference_path) = &args.compare
+        && let Some(output_buffer) = &encoded.buffer
+    {
+        let span = tracing::info_span!("compare");
+        span.pb_set_message("Comparing with reference image");
+        let comparison = span
+            .in_scope(|| compare_single(output_buffer, reference_path))
+            .wrap_err("failed to compare with reference image")?;
+        Some(comparison)
+    } else {
+        None
     };
-    let bits_per_sample = {
-        let color_type = image.original_color_type();
-        color_type.bits_per_pixel() as u32 / color_type.channel_count() as u32
+
+    Ok(EncodingStats {
+        input_format: prepared.format,
+        image_dimension: (prepared.width, prepared.height),
+        bits_per_sample: prepared.bits_per_sample,
+        is_lossless: encoded.is_lossless,
+        is_transcoded: encoded.is_transcoded,
+        num_frames: encoded.num_frames,
+        distance: (!encoded.is_transcoded).then_some(encoded.distance),
+        input_size: prepared.input_buffer.len() as u64,
+        output_size: encoded.output_size,
+        duration_read_image: prepared.duration_read_image,
+        duration_decode_image: prepared.duration_decode_image,
+        duration_encode: encoded.duration_encode,
+        duration_output: encoded.duration_output,
+        comparison,
+        auto_choice,
+        encoded: encoded.buffer.filter(|_| args.inspect),
+    })
+}
+
+fn open_output(
+    path: Option<impl AsRef<Path>>,
+    args: &Args,
+) -> eyre::Result<Option<Box<dyn Write>>> {
+    path.map(|path| -> std::io::Result<Box<dyn Write>> {
+        let path = path.as_ref();
+        Ok(if is_stdio(path) {
+            Box::new(std::io::stdout().lock())
+        } else if args.overwrite {
+            Box::new(File::create(path)?)
+        } else {
+            Box::new(File::create_new(path)?)
+        })
+    })
+    .transpose()
+    .map_err(Into::into)
+}
+
+/// Encodes the image losslessly and lossily, and returns the smaller output along with the
+/// output sizes.
+///
+/// Returns `None` as the choice if the image is transcoded, as distance doesn't apply then.
+fn choose_auto(
+    encoder: &mut jexcel::JxlEncoder,
+    prepared: &mut PreparedInput<'_>,
+    args: &Args,
+) -> eyre::Result<(Encoded, Option<AutoChoice>)> {
+    let lossless = encode_once(encoder, prepared, args, Some(0.), None, true)?;
+    if lossless.is_transcoded {
+        return Ok((lossless, None));
+    }
+
+    let lossy_distance = args
+        .distance
+        .filter(|&distance| distance > 0.)
+        .unwrap_or(1.);
+    let lossy = encode_once(encoder, prepared, args, Some(lossy_distance), None, true)?;
+    tracing::debug!(
+        lossless = lossless.output_size,
+        lossy = lossy.output_size,
+        "Encoded losslessly and lossily"
+    );
+
+    let choice = AutoChoice {
+        lossless_size: lossless.output_size,
+        lossy_size: lossy.output_size,
+        lossy_distance,
+    };
+    let encoded = if lossless.output_size <= lossy.output_size {
+        lossless
+    } else {
+        lossy
     };
+    Ok((encoded, Some(choice)))
+}
+
+/// Binary searches the smallest distance whose output fits in the target size, and returns
+/// the output encoded with it.
+fn search_distance(
+    encoder: &mut jexcel::JxlEncoder,
+    prepared: &mut PreparedInput<'_>,
+    args: &Args,
+    target: TargetSize,
+) -> eyre::Result<Encoded> {
+    const MAX_ITERATIONS: usize = 10;
+    const TOLERANCE: f64 = 0.05;
+
+    let target_bytes = target.bytes((prepared.width, prepared.height));
+    // Output size is roughly linear to the logarithm of distance.
+    let (mut low, mut high) = (0.05f32, 25f32);
+    let mut distance = 1f32;
+    let mut best = None;
+    for _ in 0..MAX_ITERATIONS {
+        let encoded = encode_once(encoder, prepared, args, Some(distance), None, true)?;
+        tracing::debug!(
+            distance,
+            size = encoded.output_size,
+            target = target_bytes,
+            "Encoded with distance"
+        );
+
+        if encoded.output_size <= target_bytes {
+            let is_close = encoded.output_size as f64 >= target_bytes as f64 * (1. - TOLERANCE);
+            best = Some(encoded);
+            if is_close {
+                break;
+            }
+            high = distance;
+        } else {
+            low = distance;
+        }
+        distance = (low * high).sqrt();
+    }
+
+    match best {
+        Some(encoded) => Ok(encoded),
+        None => {
+            tracing::warn!("Target size cannot be reached, using the largest distance");
+            encode_once(encoder, prepared, args, Some(high), None, true)
+        }
+    }
+}
+
+/// Decoded pixels of the input image.
+enum Frames {
+    /// Pixels are decoded only if JPEG transcoding fails.
+    Pending,
+    Image(Vec<u8>),
+    /// Pixels and durations in milliseconds of each frame.
+    Animation(Vec<(Vec<u8>, u32)>),
+}
+
+/// Input image read and decoded once, so that it can be encoded repeatedly.
+struct PreparedInput<'a> {
+    input: InputSource<'a>,
+    input_buffer: Cow<'a, [u8]>,
+    /// `None` if the image is generated.
+    format: Option<image::ImageFormat>,
+    width: u32,
+    height: u32,
+    orientation: image::metadata::Orientation,
+    reorient: bool,
+    icc: Option<Vec<u8>>,
+    num_channels: u32,
+    sample_format: jexcel::SampleFormat,
+    has_alpha: bool,
+    is_gray: bool,
+    bytes_per_pixel: usize,
+    bits_per_sample: u32,
+    do_transcode: bool,
+    /// Whether transcoding failed once, so that it's not tried again.
+    transcode_failed: bool,
+    frames: Frames,
+    duration_read_image: Duration,
+    duration_decode_image: Duration,
+    _memory_guard: Option<MemoryGuard<'a>>,
+}
+
+impl<'a> PreparedInput<'a> {
+    fn new(
+        input: InputSource<'a>,
+        args: &Args,
+        memory_budget: Option<&'a MemoryBudget>,
+    ) -> eyre::Result<Self> {
+        let begin_read_image = Instant::now();
+        let input_buffer = match input {
+            InputSource::File(path) => {
+                Cow::Owned(read_input(path).wrap_err("failed to read input")?)
+            }
+            InputSource::Stdin(buffer) => Cow::Borrowed(buffer),
+            InputSource::Generated(_) => Cow::Borrowed(&[][..]),
+        };
+        let duration_read_image = begin_read_image.elapsed();
+
+        let (format, mut image) = open_image(input, &input_buffer)?;
+        let num_frames = count_animation_frames(format, &input_buffer).unwrap_or(1);
+        let is_animation = num_frames > 1;
+        let orientation = image
+            .orientation()
+            .wrap_err("failed to read image orientation")?;
+        let reorient =
+            args.apply_orientation && orientation != image::metadata::Orientation::NoTransforms;
+        let is_jpeg = format == Some(image::ImageFormat::Jpeg);
+        // Distance doesn't apply to transcoded images.
+        let is_target_size = TargetSize::from_args(args).is_some();
+        let do_transcode = is_jpeg && !args.force_from_pixels && !reorient && !is_target_size;
+
+        let icc = image.icc_profile().wrap_err("failed to decode image")?;
+        let (width, height) = image.dimensions();
+        if width == 0 || height == 0 {
+            eyre::bail!("image has zero dimension ({width} x {height})");
+        }
+        // Animation frames are always decoded to RGBA.
+        let color_type = if is_animation {
+            image::ColorType::Rgba8
+        } else {
+            image.color_type()
+        };
+        let (num_channels, sample_format) = jexcel::color_type_format(color_type)
+            .ok_or_else(|| eyre::eyre!("unsupported color type {color_type:?}"))?;
+        let bytes_per_pixel = color_type.bytes_per_pixel() as usize;
+        let bits_per_sample = if is_animation {
+            8
+        } else {
+            let color_type = image.original_color_type();
+            color_type.bits_per_pixel() as u32 / color_type.channel_count() as u32
+        };
+        // Decoded pixels, plus planes of 32-bit floats used by libjxl.
+        let memory_guard = memory_budget.map(|budget| {
+            let num_pixels = width as u64 * height as u64;
+            budget.acquire(
+                num_pixels * num_frames as u64 * bytes_per_pixel as u64
+                    + num_pixels * num_channels as u64 * 4,
+            )
+        });
+
+        // Pixels are decoded after reserving memory for them.
+        let begin_decode_image = Instant::now();
+        let orient = |buffer: Vec<u8>| {
+            if reorient {
+                apply_orientation(&buffer, width, height, bytes_per_pixel, orientation)
+            } else {
+                buffer
+            }
+        };
+        let frames = if is_animation {
+            drop(image);
+            let frames = read_animation_frames(format, &input_buffer)
+                .wrap_err("failed to decode animation")?;
+            let frames = frames
+                .into_iter()
+                .map(|frame| {
+                    let (numer, denom) = frame.delay().numer_denom_ms();
+                    let duration = (numer as f64 / denom as f64).round() as u32;
+                    (orient(frame.into_buffer().into_raw()), duration)
+                })
+                .collect();
+            Frames::Animation(frames)
+        } else if do_transcode {
+            drop(image);
+            Frames::Pending
+        } else {
+            Frames::Image(orient(read_image(image)?))
+        };
+        let duration_decode_image = begin_decode_image.elapsed();
+
+        Ok(Self {
+            input,
+            input_buffer,
+            format,
+            width,
+            height,
+            orientation,
+            reorient,
+            icc,
+            num_channels,
+            sample_format,
+            has_alpha: color_type.has_alpha(),
+            is_gray: !color_type.has_color(),
+            bytes_per_pixel,
+            bits_per_sample,
+            do_transcode,
+            transcode_failed: false,
+            frames,
+            duration_read_image,
+            duration_decode_image,
+            _memory_guard: memory_guard,
+        })
+    }
+
+    fn is_animation(&self) -> bool {
+        matches!(self.frames, Frames::Animation(_))
+    }
+
+    /// Decodes pixels if they were left for JPEG transcoding.
+    fn decode_pending(&mut self) -> eyre::Result<()> {
+        if !matches!(self.frames, Frames::Pending) {
+            return Ok(());
+        }
+
+        let begin_decode_image = Instant::now();
+        let (_, image) = open_image(self.input, &self.input_buffer)?;
+        let mut buffer = read_image(image)?;
+        if self.reorient {
+            buffer = apply_orientation(
+                &buffer,
+                self.width,
+                self.height,
+                self.bytes_per_pixel,
+                self.orientation,
+            );
+        }
+        self.frames = Frames::Image(buffer);
+        self.duration_decode_image += begin_decode_image.elapsed();
+        Ok(())
+    }
+
+    /// Dimension of the encoded image, after applying orientation.
+    fn encoded_dimension(&self) -> (u32, u32) {
+        if self.reorient && orientation_swaps_axes(self.orientation) {
+            (self.height, self.width)
+        } else {
+            (self.width, self.height)
+        }
+    }
+
+    /// Returns whether pixels are dithered to 8 bits, and the sample format of encoded pixels.
+    fn encode_format(&self, is_lossless: bool, args: &Args) -> (bool, jexcel::SampleFormat) {
+        let dither = args.dither && !is_lossless && self.sample_format == jexcel::SampleFormat::U16;
+        if dither {
+            (true, jexcel::SampleFormat::U8)
+        } else {
+            (false, self.sample_format)
+        }
+    }
+
+    /// Pixels of a single frame image to be encoded, or an empty buffer if not decoded.
+    fn encode_pixels(&self, dither: bool) -> Cow<'_, [u8]> {
+        let Frames::Image(buffer) = &self.frames else {
+            return Cow::Borrowed(&[]);
+        };
+        if dither {
+            let (xsize, _) = self.encoded_dimension();
+            Cow::Owned(dither_to_u8(
+                buffer,
+                xsize,
+                self.num_channels,
+                self.has_alpha,
+            ))
+        } else {
+            Cow::Borrowed(buffer)
+        }
+    }
+}
+
+fn open_image<'buf>(
+    input: InputSource<'_>,
+    input_buffer: &'buf [u8],
+) -> eyre::Result<(Option<image::ImageFormat>, Box<dyn ImageDecoder + 'buf>)> {
+    Ok(match input {
+        InputSource::File(_) | InputSource::Stdin(_) => {
+            let image = image::ImageReader::new(std::io::Cursor::new(input_buffer))
+                .with_guessed_format()
+                .wrap_err("cannot guess image format")?;
+            let format = image.format();
+            let decoder = image.into_decoder().wrap_err("failed to parse image")?;
+            (format, Box::new(decoder))
+        }
+        InputSource::Generated(generated) => (None, Box::new(generated.clone())),
+    })
+}
+
+fn read_image(image: Box<dyn ImageDecoder + '_>) -> eyre::Result<Vec<u8>> {
+    let mut buffer = vec![0u8; image.total_bytes() as usize];
+    image
+        .read_image(&mut buffer)
+        .wrap_err("failed to decode input image")?;
+    Ok(buffer)
+}
+
+/// Output of encoding the prepared input once.
+struct Encoded {
+    is_lossless: bool,
+    /// Distance used to encode pixels, which is ignored if transcoded.
+    distance: f32,
+    is_transcoded: bool,
+    num_frames: usize,
+    output_size: u64,
+    duration_encode: Duration,
+    duration_output: Duration,
+    /// Encoded image, if it's kept in memory.
+    buffer: Option<Vec<u8>>,
+}
+
+/// Encodes the prepared input once, with the distance overridden if given.
+///
+/// The encoder is reset before encoding, so that it can be reused across probes.
+///
+/// Output is written to `output` while encoding, and also kept in memory if `keep_output` is
+/// set.
+fn encode_once(
+    encoder: &mut jexcel::JxlEncoder,
+    prepared: &mut PreparedInput<'_>,
+    args: &Args,
+    distance: Option<f32>,
+    mut output: Option<&mut dyn Write>,
+    keep_output: bool,
+) -> eyre::Result<Encoded> {
+    let distance = distance
+        .or(args.distance)
+        .unwrap_or(if args.force_modular { 0. } else { 1. });
+    // Other distances are set as-is, and rejected if out of range.
+    let is_lossless = distance == 0.;
+    let effort = jexcel::Effort::try_from(args.effort).wrap_err("invalid effort settings")?;
+    let is_modular = is_lossless || args.force_modular;
+    let (dither, encode_sample_format) = prepared.encode_format(is_lossless, args);
+    let encode_bits_per_sample = if dither { 8 } else { prepared.bits_per_sample };
+    let has_alpha = prepared.has_alpha;
+    let do_transcode = prepared.do_transcode && !prepared.transcode_failed;
 
     let mut modular_responsive = None;
     let mut lf_frames = None;
@@ -426,12 +1534,28 @@ fn encode_single(
         }
     }
 
-    let mut encoder = jexcel::JxlEncoder::new().ok_or_eyre("failed to create encoder")?;
+    encoder.reset();
+
+    if args.embed_filename
+        && let InputSource::File(path) = prepared.input
+        && let Some(file_name) = path.file_name()
+    {
+        let file_name = file_name.to_string_lossy().into_owned();
+        let filename_box = jexcel::MetadataBox::new(*b"fnam", file_name.into_bytes());
+        encoder.use_boxes()?;
+        encoder
+            .add_box(&filename_box)
+            .wrap_err("failed to add file name box")?;
+    }
 
     let settings = encoder
         .create_frame_settings_with(|settings| {
+            if is_lossless {
+                settings.lossless(true)?;
+            } else {
+                settings.distance(distance)?;
+            }
             settings
-                .distance(distance)?
                 .effort(effort)
                 .modular_progressive(modular_responsive)
                 .vardct_progressive_lf(lf_frames)?
@@ -439,6 +1563,9 @@ fn encode_single(
                 .vardct_progressive_hf_quant(progressive_hf_q)
                 .modular(if is_modular { Some(true) } else { None })
                 .decoding_speed(args.decoding_speed)?;
+            if args.lossless_alpha && has_alpha {
+                settings.extra_channel_distance(0, Some(0.))?;
+            }
             Ok(())
         })
         .wrap_err("failed to create frame settings")?;
@@ -454,89 +1581,111 @@ fn encode_single(
         let mut frame = encoder
             .add_frame(settings)
             .wrap_err("failed to add image frame")?;
-        let jpeg_result = frame.jpeg(&input_buffer);
+        let jpeg_result = frame.jpeg(&prepared.input_buffer);
 
         transcoding_ok = jpeg_result.is_ok();
         if let Err(error) = jpeg_result {
             tracing::warn!(%error, "JPEG transcoding failed, falling back to encoding pixels");
-
-            if !is_lossless {
-                do_verify = false;
-            }
+            prepared.transcode_failed = true;
         }
     }
 
-    let mut duration_decode_image = Duration::default();
-    let mut image_buffer = Vec::new();
+    let mut num_frames = 1;
     if !transcoding_ok {
         frame_guard.pb_set_message("Adding frame");
         encoder.set_jpeg_reconstruction(false)?;
+        prepared.decode_pending()?;
 
+        let (xsize, ysize) = prepared.encoded_dimension();
         let mut basic_info = jexcel::BasicInfo::new();
-        basic_info.xsize = width;
-        basic_info.ysize = height;
-        basic_info.bits_per_sample = bits_per_sample;
-        basic_info.uses_original_profile = is_lossless as i32;
+        basic_info.xsize = xsize;
+        basic_info.ysize = ysize;
+        if !args.apply_orientation {
+            basic_info.orientation = prepared.orientation.to_exif() as _;
+        }
+        basic_info.bits_per_sample = encode_bits_per_sample;
+        basic_info
+            .set_num_color_channels(if prepared.is_gray { 1 } else { 3 })
+            .wrap_err("invalid number of color channels")?;
+        basic_info.set_uses_original_profile(is_lossless);
         if has_alpha {
-            basic_info.num_extra_channels = 1;
-            basic_info.alpha_bits = bits_per_sample;
-            basic_info.alpha_premultiplied = 0;
+            basic_info.set_alpha(encode_bits_per_sample, args.premultiplied_alpha);
+        }
+        if prepared.is_animation() {
+            // Frame delays are in milliseconds. Loop count isn't available from the decoders.
+            basic_info
+                .set_animation(1000, 1, 0)
+                .wrap_err("invalid animation header")?;
+        }
+        if let Some((bits_per_sample, exponent_bits)) = encode_sample_format.float_depth() {
+            basic_info
+                .set_float_depth(bits_per_sample, exponent_bits)
+                .wrap_err("invalid float bit depth")?;
         }
 
         encoder
             .set_basic_info(&basic_info)
             .wrap_err("failed to set basic info")?;
 
-        if let Some(icc) = icc {
+        if let Some(icc) = &prepared.icc {
             encoder
-                .set_icc_profile(&icc)
+                .set_icc_profile(icc)
                 .wrap_err("failed to set color encoding")?;
         } else {
-            let color_encoding = jexcel::ColorEncoding::srgb(jexcel::RenderingIntent::Relative);
+            let intent = jexcel::RenderingIntent::Relative;
+            let color_encoding = if prepared.is_gray {
+                jexcel::ColorEncoding::srgb_gray(intent)
+            } else {
+                jexcel::ColorEncoding::srgb(intent)
+            };
             encoder
                 .set_color_encoding(&color_encoding)
                 .wrap_err("failed to set color encoding")?;
         }
 
-        let begin_decode_image = Instant::now();
-        image_buffer = vec![0u8; image.total_bytes() as usize];
-        image
-            .read_image(&mut image_buffer)
-            .wrap_err("failed to decode input image")?;
-        duration_decode_image = begin_decode_image.elapsed();
-
-        begin_encode = Instant::now();
-        encoder
-            .add_frame(settings)
-            .wrap_err("failed to add image frame")?
-            .color_channels(num_channels, sample_format, &image_buffer)
-            .wrap_err("failed to set image buffer")?;
+        if let Frames::Animation(frames) = &prepared.frames {
+            begin_encode = Instant::now();
+            num_frames = frames.len();
+            for (frame_buffer, duration) in frames {
+                let frame_header = jexcel::FrameHeader::builder()
+                    .duration(*duration)
+                    .build()
+                    .wrap_err("invalid frame header")?;
+                encoder
+                    .update_frame_settings_with(settings, |settings| {
+                        settings.frame_header(&frame_header)?;
+                        Ok(())
+                    })
+                    .wrap_err("failed to set frame header")?;
+
+                encoder
+                    .add_frame(settings)
+                    .wrap_err("failed to add image frame")?
+                    .color_channels(prepared.num_channels, encode_sample_format, frame_buffer)
+                    .wrap_err("failed to set image buffer")?;
+            }
+        } else {
+            let image_buffer = prepared.encode_pixels(dither);
 
-        if !do_verify {
-            image_buffer = Vec::new();
+            begin_encode = Instant::now();
+            encoder
+                .add_frame(settings)
+                .wrap_err("failed to add image frame")?
+                .color_channels(prepared.num_channels, encode_sample_format, &image_buffer)
+                .wrap_err("failed to set image buffer")?;
         }
     }
 
     encoder.close_input();
     frame_guard.exit();
 
-    let mut output = output_path
-        .map(|path| {
-            let path = path.as_ref();
-            if args.overwrite {
-                File::create(path)
-            } else {
-                File::create_new(path)
-            }
-        })
-        .transpose()?;
-    let mut output_buffer = do_verify.then(Vec::new);
+    let mut output_buffer = keep_output.then(Vec::new);
 
     let encode_span = tracing::info_span!("encode");
     encode_span.pb_set_message("Encoding frame");
 
     let (output_size, duration_output) = encode_span.in_scope(|| -> eyre::Result<_> {
-        let mut buffer = vec![0u8; 1024 * 1024];
+        let mut buffer = vec![0u8; args.output_buffer_size as usize];
         let mut output_size = 0u64;
         let mut duration_output = Duration::default();
 
@@ -559,50 +1708,26 @@ fn encode_single(
                 break;
             }
         }
+        if let Some(output) = &mut output {
+            let begin = Instant::now();
+            output.flush().wrap_err("failed to write output")?;
+            duration_output += begin.elapsed();
+        }
 
         Ok((output_size, duration_output))
     })?;
     drop(encode_span);
 
     let duration_encode_output = begin_encode.elapsed();
-    let duration_encode = duration_encode_output - duration_output;
-
-    if let Some(output_buffer) = output_buffer {
-        let span = tracing::info_span!("verify");
-        span.pb_set_message("Verifying encoded image");
-        let result = span.in_scope(|| {
-            let input_buffer = if transcoding_ok {
-                &input_buffer
-            } else {
-                &image_buffer
-            };
-            verify_single(
-                input_buffer,
-                &output_buffer,
-                transcoding_ok,
-                num_channels,
-                sample_format,
-            )
-        });
-
-        if let Err(err) = result {
-            tracing::error!(%err, "Encoding verification failed");
-            return Err(err);
-        }
-    }
-
-    Ok(EncodingStats {
-        input_format: format.unwrap(),
-        image_dimension: (width, height),
-        bits_per_sample,
+    Ok(Encoded {
         is_lossless,
+        distance,
         is_transcoded: transcoding_ok,
-        input_size,
+        num_frames,
         output_size,
-        duration_read_image,
-        duration_decode_image,
-        duration_encode,
+        duration_encode: duration_encode_output - duration_output,
         duration_output,
+        buffer: output_buffer,
     })
 }
 
@@ -612,6 +1737,7 @@ fn verify_single(
     is_transcoded: bool,
     num_channels: u32,
     sample_format: jexcel::SampleFormat,
+    max_error: Option<f32>,
 ) -> eyre::Result<()> {
     let mut decoder = jexcel::JxlDecoder::new().ok_or_eyre("cannot create decoder")?;
 
@@ -622,10 +1748,498 @@ fn verify_single(
         }
     } else {
         let output_image = decoder.decode_to_pixels(output_buffer, num_channels, sample_format)?;
-        if input_buffer != output_image {
+        if let Some(max_error) = max_error {
+            let errors = sample_errors(input_buffer, &output_image, num_channels, sample_format);
+            for (channel, &(max, mean)) in errors.iter().enumerate() {
+                tracing::debug!("Channel {channel}: max error {max:.5}, mean error {mean:.5}");
+                if max > max_error as f64 {
+                    eyre::bail!(
+                        "error of channel {channel} exceeds tolerance: max {max:.5}, mean {mean:.5}"
+                    );
+                }
+            }
+        } else if input_buffer != output_image {
             eyre::bail!("output pixel mismatch");
         }
     }
 
     Ok(())
 }
+
+/// Computes maximum and mean absolute errors of each channel, with samples normalized to
+/// `0..=1`.
+fn sample_errors(
+    a: &[u8],
+    b: &[u8],
+    num_channels: u32,
+    sample_format: jexcel::SampleFormat,
+) -> Vec<(f64, f64)> {
+    fn to_samples(buffer: &[u8], sample_format: jexcel::SampleFormat) -> Vec<f64> {
+        let bytes_per_sample = sample_format.bytes_per_sample();
+        buffer
+            .chunks_exact(bytes_per_sample)
+            .map(|sample| match sample_format {
+                jexcel::SampleFormat::U8 => sample[0] as f64 / 255.,
+                jexcel::SampleFormat::U16 => {
+                    u16::from_ne_bytes([sample[0], sample[1]]) as f64 / 65535.
+                }
+                jexcel::SampleFormat::F16 => {
+                    half::f16::from_ne_bytes([sample[0], sample[1]]).to_f64()
+                }
+                jexcel::SampleFormat::F32 => {
+                    f32::from_ne_bytes([sample[0], sample[1], sample[2], sample[3]]) as f64
+                }
+            })
+            .collect()
+    }
+
+    let num_channels = num_channels as usize;
+    let mut max = vec![0f64; num_channels];
+    let mut sum = vec![0f64; num_channels];
+    let a = to_samples(a, sample_format);
+    let b = to_samples(b, sample_format);
+    for (a, b) in a
+        .chunks_exact(num_channels)
+        .zip(b.chunks_exact(num_channels))
+    {
+        for (channel, (a, b)) in a.iter().zip(b).enumerate() {
+            let diff = (a - b).abs();
+            max[channel] = max[channel].max(diff);
+            sum[channel] += diff;
+        }
+    }
+
+    let num_pixels = (a.len() / num_channels).max(1) as f64;
+    max.into_iter()
+        .zip(sum)
+        .map(|(max, sum)| (max, sum / num_pixels))
+        .collect()
+}
+
+fn decode_single(args: &DecodeArgs) -> eyre::Result<()> {
+    let output_is_stdout = is_stdio(&args.output);
+    if output_is_stdout {
+        if std::io::stdout().is_terminal() {
+            eyre::bail!("refusing to write image to terminal");
+        }
+    } else {
+        ensure_file_inexist(&args.output, args.overwrite)
+            .wrap_err_with(|| format!("error checking path \"{}\"", args.output.display()))?;
+    }
+
+    let input_buffer = read_input(&args.input).wrap_err("failed to read input")?;
+    if !matches!(
+        jexcel::check_signature(&input_buffer),
+        jexcel::Signature::Codestream | jexcel::Signature::Container
+    ) {
+        eyre::bail!("input is not a JPEG XL image");
+    }
+
+    let format = if output_is_stdout {
+        None
+    } else {
+        Some(
+            image::ImageFormat::from_path(&args.output)
+                .wrap_err("cannot determine output format")?,
+        )
+    };
+
+    let mut decoder = jexcel::JxlDecoder::new().ok_or_eyre("cannot create decoder")?;
+    let mut output_buffer = None;
+    if matches!(format, None | Some(image::ImageFormat::Jpeg)) {
+        match decoder.decode_to_jpeg(&input_buffer) {
+            Ok(jpeg) => output_buffer = Some(jpeg),
+            Err(jexcel::Error::NotJpegReconstructible) => {
+                if format.is_some() {
+                    tracing::warn!("JPEG cannot be reconstructed, encoding decoded pixels");
+                }
+            }
+            Err(err) => return Err(err).wrap_err("failed to reconstruct JPEG"),
+        }
+    }
+
+    let output_buffer = match output_buffer {
+        Some(buffer) => buffer,
+        None => {
+            let format = format.unwrap_or(image::ImageFormat::Png);
+            let mut image = jexcel::decode_to_dynamic_image(&input_buffer)
+                .wrap_err("failed to decode image")?;
+            if !args.keep_orientation {
+                let orientation = decoder.basic_info(&input_buffer)?.orientation;
+                let orientation = image::metadata::Orientation::from_exif(orientation as u8)
+                    .unwrap_or(image::metadata::Orientation::NoTransforms);
+                image.apply_orientation(orientation);
+            }
+
+            // Most formats don't support floating point samples.
+            let has_alpha = image.color().has_alpha();
+            if format == image::ImageFormat::Jpeg {
+                image = image::DynamicImage::ImageRgb8(image.to_rgb8());
+            } else if matches!(
+                image.color(),
+                image::ColorType::Rgb32F | image::ColorType::Rgba32F
+            ) {
+                image = if has_alpha {
+                    image::DynamicImage::ImageRgba16(image.to_rgba16())
+                } else {
+                    image::DynamicImage::ImageRgb16(image.to_rgb16())
+                };
+            }
+
+            let mut buffer = std::io::Cursor::new(Vec::new());
+            image
+                .write_to(&mut buffer, format)
+                .wrap_err("failed to encode output image")?;
+            buffer.into_inner()
+        }
+    };
+
+    let mut output: Box<dyn Write> = if output_is_stdout {
+        Box::new(std::io::stdout().lock())
+    } else if args.overwrite {
+        Box::new(File::create(&args.output)?)
+    } else {
+        Box::new(File::create_new(&args.output)?)
+    };
+    output
+        .write_all(&output_buffer)
+        .and_then(|_| output.flush())
+        .wrap_err("failed to write output")?;
+
+    tracing::info!("Decoded to {} bytes", output_buffer.len());
+    Ok(())
+}
+
+/// Prints structure of the JPEG XL image to stdout.
+fn inspect_jxl(buffer: &[u8]) -> eyre::Result<()> {
+    let signature = jexcel::check_signature(buffer);
+    println!("Signature: {signature:?}");
+
+    let mut level = 5;
+    if signature == jexcel::Signature::Container {
+        println!("Boxes:");
+        let mut remaining = buffer;
+        let mut offset = 0usize;
+        while !remaining.is_empty() {
+            let (box_type, header_size, box_size) = parse_box_header(remaining)
+                .ok_or_else(|| eyre::eyre!("invalid box header at offset {offset}"))?;
+            let contents = &remaining[header_size..box_size];
+
+            let box_type_str = String::from_utf8_lossy(&box_type);
+            if &box_type == b"brob" && contents.len() >= 4 {
+                let inner_type = String::from_utf8_lossy(&contents[..4]);
+                println!("  {offset:>10}: {box_type_str} ({inner_type}), {box_size} bytes");
+            } else {
+                println!("  {offset:>10}: {box_type_str}, {box_size} bytes");
+            }
+
+            if &box_type == b"jxll"
+                && let Some(&box_level) = contents.first()
+            {
+                level = box_level;
+            }
+
+            remaining = &remaining[box_size..];
+            offset += box_size;
+        }
+    }
+    println!("Codestream level: {level}");
+
+    let mut decoder = jexcel::JxlDecoder::new().ok_or_eyre("cannot create decoder")?;
+    let basic_info = decoder.basic_info(buffer)?;
+    println!("Basic info:");
+    println!("  Dimension: {} x {}", basic_info.xsize, basic_info.ysize);
+    println!(
+        "  Bits per sample: {} ({} exponent bits)",
+        basic_info.bits_per_sample, basic_info.exponent_bits_per_sample,
+    );
+    println!("  Color channels: {}", basic_info.num_color_channels);
+    println!(
+        "  Extra channels: {} (alpha bits: {}, premultiplied: {})",
+        basic_info.num_extra_channels,
+        basic_info.alpha_bits,
+        basic_info.alpha_premultiplied != 0,
+    );
+    println!(
+        "  Original color profile: {}",
+        basic_info.uses_original_profile != 0
+    );
+    println!("  Orientation: {:?}", basic_info.orientation());
+    println!("  Animated: {}", basic_info.have_animation != 0);
+
+    let frame_headers = decoder.frame_headers(buffer)?;
+    println!("Frames: {}", frame_headers.len());
+    for (idx, frame_header) in frame_headers.iter().enumerate() {
+        let layer_info = &frame_header.layer_info;
+        println!(
+            "  #{idx}: {} x {} at ({}, {}), duration {}{}",
+            layer_info.xsize,
+            layer_info.ysize,
+            layer_info.crop_x0,
+            layer_info.crop_y0,
+            frame_header.duration,
+            if frame_header.is_last != 0 {
+                ", last"
+            } else {
+                ""
+            },
+        );
+    }
+
+    Ok(())
+}
+
+/// Parses BMFF box header, and returns box type, header size and total box size.
+fn parse_box_header(buffer: &[u8]) -> Option<([u8; 4], usize, usize)> {
+    let size = u32::from_be_bytes(buffer.get(..4)?.try_into().unwrap()) as u64;
+    let box_type: [u8; 4] = buffer.get(4..8)?.try_into().unwrap();
+    let (header_size, box_size) = match size {
+        // Box extends to the end of the file.
+        0 => (8, buffer.len() as u64),
+        1 => {
+            let size = u64::from_be_bytes(buffer.get(8..16)?.try_into().unwrap());
+            (16, size)
+        }
+        size => (8, size),
+    };
+
+    let box_size = usize::try_from(box_size).ok()?;
+    if box_size < header_size || box_size > buffer.len() {
+        return None;
+    }
+    Some((box_type, header_size, box_size))
+}
+
+fn compare_single(output_buffer: &[u8], reference_path: &Path) -> eyre::Result<Comparison> {
+    let reference_buffer = std::fs::read(reference_path).wrap_err("failed to read reference")?;
+    let mut decoder = jexcel::JxlDecoder::new().ok_or_eyre("cannot create decoder")?;
+
+    let output_info = decoder.basic_info(output_buffer)?;
+    let reference_info = decoder.basic_info(&reference_buffer)?;
+    if (output_info.xsize, output_info.ysize) != (reference_info.xsize, reference_info.ysize) {
+        eyre::bail!(
+            "image dimension mismatch: {} x {} vs reference {} x {}",
+            output_info.xsize,
+            output_info.ysize,
+            reference_info.xsize,
+            reference_info.ysize,
+        );
+    }
+
+    // Decode both to RGBA so that grayscale and color images can be compared, and compute PSNR
+    // of color samples only.
+    let output_image = decoder.decode_to_pixels(output_buffer, 4, jexcel::SampleFormat::F32)?;
+    let reference_image =
+        decoder.decode_to_pixels(&reference_buffer, 4, jexcel::SampleFormat::F32)?;
+    let to_samples = |buffer: &[u8]| -> Vec<f32> {
+        buffer
+            .chunks_exact(4)
+            .map(|sample| f32::from_ne_bytes([sample[0], sample[1], sample[2], sample[3]]))
+            .collect()
+    };
+    let output_samples = to_samples(&output_image);
+    let reference_samples = to_samples(&reference_image);
+
+    let mut sum_sq = 0f64;
+    let mut count = 0u64;
+    for (output, reference) in output_samples
+        .chunks_exact(4)
+        .zip(reference_samples.chunks_exact(4))
+    {
+        for (a, b) in output[..3].iter().zip(&reference[..3]) {
+            let diff = a.clamp(0., 1.) as f64 - b.clamp(0., 1.) as f64;
+            sum_sq += diff * diff;
+            count += 1;
+        }
+    }
+    let mse = sum_sq / count as f64;
+    let psnr = -10. * mse.log10();
+
+    Ok(Comparison {
+        reference_size: reference_buffer.len() as u64,
+        psnr,
+    })
+}
+
+fn orientation_swaps_axes(orientation: image::metadata::Orientation) -> bool {
+    use image::metadata::Orientation;
+
+    matches!(
+        orientation,
+        Orientation::Rotate90
+            | Orientation::Rotate270
+            | Orientation::Rotate90FlipH
+            | Orientation::Rotate270FlipH
+    )
+}
+
+/// Rotates and flips interleaved pixels so that they're displayed upright without orientation.
+fn apply_orientation(
+    buffer: &[u8],
+    width: u32,
+    height: u32,
+    bytes_per_pixel: usize,
+    orientation: image::metadata::Orientation,
+) -> Vec<u8> {
+    use image::metadata::Orientation;
+
+    let (width, height) = (width as usize, height as usize);
+    let out_width = if orientation_swaps_axes(orientation) {
+        height
+    } else {
+        width
+    };
+
+    let mut out = vec![0u8; buffer.len()];
+    for (idx, pixel) in buffer.chunks_exact(bytes_per_pixel).enumerate() {
+        let (x, y) = (idx % width, idx / width);
+        let (out_x, out_y) = match orientation {
+            Orientation::NoTransforms => (x, y),
+            Orientation::FlipHorizontal => (width - 1 - x, y),
+            Orientation::Rotate180 => (width - 1 - x, height - 1 - y),
+            Orientation::FlipVertical => (x, height - 1 - y),
+            Orientation::Rotate90 => (height - 1 - y, x),
+            Orientation::Rotate270 => (y, width - 1 - x),
+            Orientation::Rotate90FlipH => (y, x),
+            Orientation::Rotate270FlipH => (height - 1 - y, width - 1 - x),
+        };
+        let out_idx = (out_y * out_width + out_x) * bytes_per_pixel;
+        out[out_idx..][..bytes_per_pixel].copy_from_slice(pixel);
+    }
+    out
+}
+
+/// Reduces native-endian 16-bit samples to 8 bits using 8x8 ordered dithering.
+///
+/// Alpha channel, if any, is rounded without dithering.
+fn dither_to_u8(buffer: &[u8], width: u32, num_channels: u32, has_alpha: bool) -> Vec<u8> {
+    const BAYER_8X8: [[u8; 8]; 8] = [
+        [0, 32, 8, 40, 2, 34, 10, 42],
+        [48, 16, 56, 24, 50, 18, 58, 26],
+        [12, 44, 4, 36, 14, 46, 6, 38],
+        [60, 28, 52, 20, 62, 30, 54, 22],
+        [3, 35, 11, 43, 1, 33, 9, 41],
+        [51, 19, 59, 27, 49, 17, 57, 25],
+        [15, 47, 7, 39, 13, 45, 5, 37],
+        [63, 31, 55, 23, 61, 29, 53, 21],
+    ];
+
+    let num_channels = num_channels as usize;
+    let width = width as usize;
+    buffer
+        .chunks_exact(2)
+        .enumerate()
+        .map(|(idx, sample)| {
+            let value = u16::from_ne_bytes([sample[0], sample[1]]) as f32 * (255. / 65535.);
+            let channel = idx % num_channels;
+            if has_alpha && channel == num_channels - 1 {
+                return value.round() as u8;
+            }
+
+            let pixel = idx / num_channels;
+            let (x, y) = (pixel % width, pixel / width);
+            let threshold = (BAYER_8X8[y % 8][x % 8] as f32 + 0.5) / 64.;
+            (value + threshold).floor().min(255.) as u8
+        })
+        .collect()
+}
+
+/// Test pattern generated in memory, parsed from `<pattern>:<width>x<height>`.
+#[derive(Debug, Clone)]
+struct GeneratedImage {
+    pattern: Pattern,
+    width: u32,
+    height: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Pattern {
+    /// Mid-gray solid color.
+    Solid,
+    /// Red increasing to the right, green increasing downwards.
+    Gradient,
+    /// Black and white checkerboard of 32x32 squares.
+    Checker,
+    /// Deterministic pseudorandom noise.
+    Noise,
+}
+
+impl std::str::FromStr for GeneratedImage {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (pattern, size) = s
+            .split_once(':')
+            .ok_or_else(|| String::from("expected <pattern>:<width>x<height>"))?;
+        let pattern = match pattern {
+            "solid" => Pattern::Solid,
+            "gradient" => Pattern::Gradient,
+            "checker" => Pattern::Checker,
+            "noise" => Pattern::Noise,
+            _ => return Err(format!("unknown pattern `{pattern}`")),
+        };
+        let (width, height) = size
+            .split_once('x')
+            .ok_or_else(|| String::from("expected image size of <width>x<height>"))?;
+        let width = width
+            .parse::<u32>()
+            .map_err(|err| format!("invalid width: {err}"))?;
+        let height = height
+            .parse::<u32>()
+            .map_err(|err| format!("invalid height: {err}"))?;
+
+        Ok(Self {
+            pattern,
+            width,
+            height,
+        })
+    }
+}
+
+impl GeneratedImage {
+    fn fill(&self, buf: &mut [u8]) {
+        let width = self.width as usize;
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        for (idx, pixel) in buf.chunks_exact_mut(3).enumerate() {
+            let (x, y) = (idx % width, idx / width);
+            match self.pattern {
+                Pattern::Solid => pixel.fill(128),
+                Pattern::Gradient => {
+                    let r = x * 255 / (width - 1).max(1);
+                    let g = y * 255 / (self.height as usize - 1).max(1);
+                    pixel.copy_from_slice(&[r as u8, g as u8, 128]);
+                }
+                Pattern::Checker => {
+                    let value = if (x / 32 + y / 32) % 2 == 0 { 255 } else { 0 };
+                    pixel.fill(value);
+                }
+                Pattern::Noise => {
+                    // xorshift64
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    pixel.copy_from_slice(&state.to_le_bytes()[..3]);
+                }
+            }
+        }
+    }
+}
+
+impl ImageDecoder for GeneratedImage {
+    fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn color_type(&self) -> image::ColorType {
+        image::ColorType::Rgb8
+    }
+
+    fn read_image(self, buf: &mut [u8]) -> image::ImageResult<()> {
+        self.fill(buf);
+        Ok(())
+    }
+
+    fn read_image_boxed(self: Box<Self>, buf: &mut [u8]) -> image::ImageResult<()> {
+        (*self).read_image(buf)
+    }
+}
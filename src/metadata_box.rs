@@ -0,0 +1,58 @@
+/// Box types which are written by libjxl itself, and cannot be added with
+/// [`JxlEncoder::add_box`][crate::JxlEncoder::add_box].
+const RESERVED_BOX_TYPES: [[u8; 4]; 7] = [
+    *b"JXL ", *b"ftyp", *b"jxll", *b"jxlc", *b"jxlp", *b"jxli", *b"jbrd",
+];
+
+/// Metadata box of a JPEG XL container, such as Exif, XMP or JUMBF.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MetadataBox {
+    box_type: [u8; 4],
+    pub(crate) contents: Vec<u8>,
+    compressed: bool,
+}
+
+impl MetadataBox {
+    pub fn new(box_type: [u8; 4], contents: Vec<u8>) -> Self {
+        Self {
+            box_type,
+            contents,
+            compressed: false,
+        }
+    }
+
+    /// Sets whether the box is stored Brotli-compressed in a `brob` box.
+    pub fn set_compressed(&mut self, compressed: bool) -> &mut Self {
+        self.compressed = compressed;
+        self
+    }
+
+    #[inline]
+    pub fn box_type(&self) -> [u8; 4] {
+        self.box_type
+    }
+
+    /// Uncompressed contents of the box, or stored contents for boxes read with
+    /// [`JxlDecoder::raw_boxes`][crate::JxlDecoder::raw_boxes].
+    #[inline]
+    pub fn contents(&self) -> &[u8] {
+        &self.contents
+    }
+
+    #[inline]
+    pub fn into_contents(self) -> Vec<u8> {
+        self.contents
+    }
+
+    #[inline]
+    pub fn is_compressed(&self) -> bool {
+        self.compressed
+    }
+
+    /// Whether the box type is one of the signature, codestream or JPEG reconstruction boxes,
+    /// which are managed by libjxl.
+    #[inline]
+    pub fn is_reserved_type(box_type: [u8; 4]) -> bool {
+        RESERVED_BOX_TYPES.contains(&box_type)
+    }
+}
@@ -1,8 +1,43 @@
 use std::ffi::c_void;
-use std::ptr::NonNull;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 use crate::sys;
 
+/// Flag to cancel encoding from another thread.
+///
+/// Cloned tokens share the same flag.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Context passed to the parallel runner as its opaque pointer.
+#[derive(Debug, Default)]
+pub(crate) struct RunnerContext {
+    pub(crate) cancellation: Option<CancellationToken>,
+}
+
+impl RunnerContext {
+    fn is_cancelled(&self) -> bool {
+        self.cancellation
+            .as_ref()
+            .is_some_and(CancellationToken::is_cancelled)
+    }
+}
+
 #[derive(Copy, Clone)]
 struct UnsafeAssumeSendSync<T>(T);
 unsafe impl<T> Send for UnsafeAssumeSendSync<T> {}
@@ -18,29 +53,37 @@ impl<T> UnsafeAssumeSendSync<T> {
     }
 }
 
+/// Runs tasks in the global rayon thread pool.
+///
+/// `runner_opaque` is either null or a pointer to [`RunnerContext`]. Remaining tasks are skipped
+/// and an error is returned if encoding is cancelled.
+///
+/// libjxl allocates per-thread state for the number of threads reported to `init`, so if there
+/// are fewer tasks than threads, only as many threads as tasks are reported, and a single task
+/// runs in the calling thread.
 pub(crate) unsafe extern "C" fn rayon_parallel_runner(
-    pool: *mut c_void,
+    runner_opaque: *mut c_void,
     jxl_opaque: *mut c_void,
     init: sys::JxlParallelRunInit,
     func: sys::JxlParallelRunFunction,
     start_range: u32,
     end_range: u32,
 ) -> sys::JxlParallelRetCode {
-    let pool = NonNull::new(pool as *mut rayon::ThreadPool);
+    let context = unsafe { (runner_opaque as *const RunnerContext).as_ref() };
     let jxl_opaque = unsafe { UnsafeAssumeSendSync::new(jxl_opaque) };
     let range = start_range..end_range;
 
-    unsafe {
-        if let Some(pool) = pool {
-            let pool = pool.as_ref();
-            pool.install(|| run_inner(jxl_opaque, init, func, range))
-        } else {
-            run_inner(jxl_opaque, init, func, range)
-        }
-    }
+    unsafe { run_inner(context, jxl_opaque, init, func, range) }
 }
 
+/// Calls `init` once with the number of threads, then `func` for each index in the range.
+///
+/// libjxl allocates per-thread state in `init`, which is indexed by the thread ID passed to
+/// `func`. Thread IDs are less than the reported number of threads, and unique among
+/// concurrent calls: a rayon worker runs one task at a time, and only steals other tasks while
+/// waiting outside of `func`.
 unsafe fn run_inner(
+    context: Option<&RunnerContext>,
     jxl_opaque: UnsafeAssumeSendSync<*mut c_void>,
     init: sys::JxlParallelRunInit,
     func: sys::JxlParallelRunFunction,
@@ -55,20 +98,205 @@ unsafe fn run_inner(
         return sys::JXL_PARALLEL_RET_RUNNER_ERROR as sys::JxlParallelRetCode;
     };
     let func = unsafe { UnsafeAssumeSendSync::new(func) };
+    let is_cancelled = || context.is_some_and(RunnerContext::is_cancelled);
 
-    let ret = unsafe { init(jxl_opaque.0, rayon::current_num_threads()) };
+    if is_cancelled() {
+        return sys::JXL_PARALLEL_RET_RUNNER_ERROR as sys::JxlParallelRetCode;
+    }
+
+    let num_tasks = range.len();
+    let num_threads = rayon::current_num_threads();
+    if num_tasks < num_threads {
+        let ret = unsafe { init(jxl_opaque.0, num_tasks.max(1)) };
+        if ret != 0 {
+            return ret;
+        }
+
+        // Each task gets its own thread ID, as there are fewer tasks than reported threads.
+        let start = range.start;
+        let run = |idx: u32| unsafe {
+            if is_cancelled() {
+                return;
+            }
+
+            let func = func.into_inner();
+            func(jxl_opaque.into_inner(), idx, (idx - start) as usize);
+        };
+        if num_tasks == 1 {
+            run(start);
+        } else {
+            range.into_par_iter().for_each(run);
+        }
+    } else {
+        let ret = unsafe { init(jxl_opaque.0, num_threads) };
+        if ret != 0 {
+            return ret;
+        }
+
+        range.into_par_iter().for_each(|idx| unsafe {
+            if is_cancelled() {
+                return;
+            }
+
+            // Tasks run in workers of the pool `num_threads` is queried from.
+            let thread_id = rayon::current_thread_index().unwrap_or(0);
+            debug_assert!(thread_id < num_threads);
+            let func = func.into_inner();
+            func(jxl_opaque.into_inner(), idx, thread_id);
+        });
+    }
+
+    if is_cancelled() {
+        return sys::JXL_PARALLEL_RET_RUNNER_ERROR as sys::JxlParallelRetCode;
+    }
+    sys::JXL_PARALLEL_RET_SUCCESS as sys::JxlParallelRetCode
+}
+
+/// Runs tasks sequentially in the calling thread.
+///
+/// `runner_opaque` is either null or a pointer to [`RunnerContext`].
+pub(crate) unsafe extern "C" fn serial_parallel_runner(
+    runner_opaque: *mut c_void,
+    jxl_opaque: *mut c_void,
+    init: sys::JxlParallelRunInit,
+    func: sys::JxlParallelRunFunction,
+    start_range: u32,
+    end_range: u32,
+) -> sys::JxlParallelRetCode {
+    let context = unsafe { (runner_opaque as *const RunnerContext).as_ref() };
+    let is_cancelled = || context.is_some_and(RunnerContext::is_cancelled);
+
+    let Some(init) = init else {
+        return sys::JXL_PARALLEL_RET_RUNNER_ERROR as sys::JxlParallelRetCode;
+    };
+    let Some(func) = func else {
+        return sys::JXL_PARALLEL_RET_RUNNER_ERROR as sys::JxlParallelRetCode;
+    };
+
+    let ret = unsafe { init(jxl_opaque, 1) };
     if ret != 0 {
         return ret;
     }
 
-    range.into_par_iter().for_each(|idx| unsafe {
-        let func = func.into_inner();
-        func(
-            jxl_opaque.into_inner(),
-            idx,
-            rayon::current_thread_index().unwrap_or(0),
-        );
-    });
+    for idx in start_range..end_range {
+        if is_cancelled() {
+            return sys::JXL_PARALLEL_RET_RUNNER_ERROR as sys::JxlParallelRetCode;
+        }
+        unsafe { func(jxl_opaque, idx, 0) };
+    }
 
     sys::JXL_PARALLEL_RET_SUCCESS as sys::JxlParallelRetCode
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+
+    use super::*;
+
+    /// State shared with the callbacks, recording how the runner calls them.
+    ///
+    /// Callbacks can't panic across the FFI boundary, so violations are counted instead.
+    struct Counters {
+        init_calls: AtomicUsize,
+        num_threads: AtomicUsize,
+        func_calls: Vec<AtomicUsize>,
+        start: u32,
+        busy: Vec<AtomicBool>,
+        bad_thread_ids: AtomicUsize,
+        overlaps: AtomicUsize,
+    }
+
+    impl Counters {
+        fn new(range: std::ops::Range<u32>) -> Self {
+            Self {
+                init_calls: AtomicUsize::new(0),
+                num_threads: AtomicUsize::new(0),
+                func_calls: range.clone().map(|_| AtomicUsize::new(0)).collect(),
+                start: range.start,
+                busy: (0..rayon::current_num_threads().max(1))
+                    .map(|_| AtomicBool::new(false))
+                    .collect(),
+                bad_thread_ids: AtomicUsize::new(0),
+                overlaps: AtomicUsize::new(0),
+            }
+        }
+    }
+
+    unsafe extern "C" fn init(opaque: *mut c_void, num_threads: usize) -> sys::JxlParallelRetCode {
+        let counters = unsafe { &*(opaque as *const Counters) };
+        counters.init_calls.fetch_add(1, Ordering::SeqCst);
+        counters.num_threads.store(num_threads, Ordering::SeqCst);
+        0
+    }
+
+    unsafe extern "C" fn func(opaque: *mut c_void, value: u32, thread_id: usize) {
+        let counters = unsafe { &*(opaque as *const Counters) };
+        counters.func_calls[(value - counters.start) as usize].fetch_add(1, Ordering::SeqCst);
+
+        let num_threads = counters.num_threads.load(Ordering::SeqCst);
+        let Some(busy) = counters
+            .busy
+            .get(thread_id)
+            .filter(|_| thread_id < num_threads)
+        else {
+            counters.bad_thread_ids.fetch_add(1, Ordering::SeqCst);
+            return;
+        };
+        if busy.swap(true, Ordering::SeqCst) {
+            counters.overlaps.fetch_add(1, Ordering::SeqCst);
+        }
+        // Gives other tasks a chance to run with the same thread ID.
+        std::thread::yield_now();
+        busy.store(false, Ordering::SeqCst);
+    }
+
+    fn run(runner: sys::JxlParallelRunner, range: std::ops::Range<u32>) -> Counters {
+        let counters = Counters::new(range.clone());
+        let runner = runner.unwrap();
+        let ret = unsafe {
+            runner(
+                std::ptr::null_mut(),
+                (&counters as *const Counters).cast_mut().cast(),
+                Some(init),
+                Some(func),
+                range.start,
+                range.end,
+            )
+        };
+        assert_eq!(
+            ret,
+            sys::JXL_PARALLEL_RET_SUCCESS as sys::JxlParallelRetCode
+        );
+        counters
+    }
+
+    fn assert_calls(counters: &Counters, num_threads: usize) {
+        assert_eq!(counters.init_calls.load(Ordering::SeqCst), 1);
+        assert_eq!(counters.num_threads.load(Ordering::SeqCst), num_threads);
+        for calls in &counters.func_calls {
+            assert_eq!(calls.load(Ordering::SeqCst), 1);
+        }
+        assert_eq!(counters.bad_thread_ids.load(Ordering::SeqCst), 0);
+        assert_eq!(counters.overlaps.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn rayon_runner() {
+        let max_threads = rayon::current_num_threads();
+        for range in [0..1, 3..5, 10..10 + max_threads as u32, 7..1000] {
+            // Only as many threads as tasks are reported if there are fewer tasks.
+            let num_threads = range.len().min(max_threads);
+            let counters = run(Some(rayon_parallel_runner), range);
+            assert_calls(&counters, num_threads);
+        }
+    }
+
+    #[test]
+    fn serial_runner() {
+        for range in [0..1, 7..1000] {
+            let counters = run(Some(serial_parallel_runner), range);
+            assert_calls(&counters, 1);
+        }
+    }
+}
@@ -0,0 +1,35 @@
+use crate::sys;
+
+/// Version of the linked libjxl encoder, in the form of `MAJOR * 1000000 + MINOR * 1000 + PATCH`.
+pub fn encoder_version() -> u32 {
+    unsafe { sys::JxlEncoderVersion() }
+}
+
+/// Version of the linked libjxl decoder, in the form of `MAJOR * 1000000 + MINOR * 1000 + PATCH`.
+pub fn decoder_version() -> u32 {
+    unsafe { sys::JxlDecoderVersion() }
+}
+
+/// Version of the linked libjxl formatted as `MAJOR.MINOR.PATCH`.
+///
+/// Decoder version is appended if it differs from the encoder version.
+pub fn version_string() -> String {
+    fn format_version(version: u32) -> String {
+        let major = version / 1_000_000;
+        let minor = version / 1000 % 1000;
+        let patch = version % 1000;
+        format!("{major}.{minor}.{patch}")
+    }
+
+    let encoder = encoder_version();
+    let decoder = decoder_version();
+    if encoder == decoder {
+        format_version(encoder)
+    } else {
+        format!(
+            "{} (decoder {})",
+            format_version(encoder),
+            format_version(decoder)
+        )
+    }
+}
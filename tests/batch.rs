@@ -0,0 +1,47 @@
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[test]
+fn batch_encode_reports_each_file() {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("batch_encode");
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let image = image::RgbImage::from_fn(16, 16, |x, y| {
+        image::Rgb([(x * 16) as u8, (y * 16) as u8, 0])
+    });
+    let png_path = dir.join("image.png");
+    image.save(&png_path).unwrap();
+    let jpg_path = dir.join("image.jpg");
+    image.save(&jpg_path).unwrap();
+    let missing_path = dir.join("missing.png");
+
+    let paths = [png_path, missing_path, jpg_path];
+    let num_done = AtomicUsize::new(0);
+    let results = jexcel::batch_encode(&paths, &jexcel::EncodeOptions::default(), |_| {
+        num_done.fetch_add(1, Ordering::Relaxed);
+    });
+    assert_eq!(num_done.into_inner(), paths.len());
+
+    assert_eq!(results.len(), paths.len());
+    for (result, path) in results.iter().zip(&paths) {
+        assert_eq!(&result.input, path);
+    }
+
+    let png = results[0].result.as_ref().expect("failed to encode PNG");
+    assert!(!results[0].is_transcoded);
+    let mut decoder = jexcel::JxlDecoder::new().expect("failed to create decoder");
+    let basic_info = decoder.basic_info(png).expect("failed to decode output");
+    assert_eq!((basic_info.xsize, basic_info.ysize), (16, 16));
+
+    assert!(matches!(results[1].result, Err(jexcel::Error::Io(_))));
+
+    let jpg = results[2]
+        .result
+        .as_ref()
+        .expect("failed to transcode JPEG");
+    assert!(results[2].is_transcoded);
+    let reconstructed = decoder
+        .decode_to_jpeg(jpg)
+        .expect("failed to reconstruct JPEG");
+    assert!(reconstructed == std::fs::read(&paths[2]).unwrap());
+}
@@ -0,0 +1,51 @@
+mod common;
+
+const CMYK_ICC: &[u8] = include_bytes!("data/cmyk.icc");
+
+fn encode_cmyk(width: u32, height: u32, cmyk: &[u8]) -> jexcel::Result<Vec<u8>> {
+    let mut encoder = jexcel::JxlEncoder::new().expect("failed to create encoder");
+    encoder.set_codestream_level(Some(10))?;
+
+    let mut basic_info = common::basic_info(width, height);
+    basic_info.num_extra_channels = 1;
+    encoder.set_basic_info(&basic_info)?;
+    encoder.set_icc_profile(CMYK_ICC)?;
+    encoder.set_extra_channel_info(
+        0,
+        &jexcel::ExtraChannelInfo::new(jexcel::ExtraChannelType::Black),
+    )?;
+
+    let settings = encoder.create_frame_settings_with(|settings| {
+        settings.lossless(true)?;
+        Ok(())
+    })?;
+    encoder
+        .add_frame(settings)?
+        .cmyk_channels(0, jexcel::SampleFormat::U8, cmyk)?;
+    encoder.close_input();
+
+    common::collect_output(&mut encoder)
+}
+
+#[test]
+fn cmyk_roundtrip() {
+    let (width, height) = (16u32, 12u32);
+    let cmyk = (0..height)
+        .flat_map(|y| (0..width).map(move |x| [(x * 16) as u8, (y * 20) as u8, 128, (x * y) as u8]))
+        .flatten()
+        .collect::<Vec<_>>();
+
+    let jxl = encode_cmyk(width, height, &cmyk).expect("failed to encode CMYK image");
+
+    let mut decoder = jexcel::JxlDecoder::new().expect("failed to create decoder");
+    let infos = decoder
+        .extra_channel_infos(&jxl)
+        .expect("failed to read extra channel info");
+    assert_eq!(infos.len(), 1);
+    assert_eq!(infos[0].channel_type(), jexcel::ExtraChannelType::Black);
+
+    let decoded = decoder
+        .decode_to_cmyk(&jxl, jexcel::SampleFormat::U8)
+        .expect("failed to decode CMYK image");
+    assert!(decoded == cmyk, "output pixel mismatch");
+}
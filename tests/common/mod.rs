@@ -0,0 +1,64 @@
+//! Encoding helpers shared by integration tests.
+
+// Each test crate uses only some of the helpers.
+#![allow(dead_code)]
+
+/// Returns basic info of an 8-bit image, which keeps the original profile for lossless
+/// encoding.
+pub fn basic_info(width: u32, height: u32) -> jexcel::BasicInfo {
+    let mut basic_info = jexcel::BasicInfo::new();
+    basic_info.xsize = width;
+    basic_info.ysize = height;
+    basic_info.set_uses_original_profile(true);
+    basic_info
+}
+
+/// Sets basic info, and sRGB as the color encoding.
+pub fn set_srgb(
+    encoder: &mut jexcel::JxlEncoder,
+    basic_info: &jexcel::BasicInfo,
+) -> jexcel::Result<()> {
+    encoder.set_basic_info(basic_info)?;
+    encoder.set_color_encoding(&jexcel::ColorEncoding::srgb(
+        jexcel::RenderingIntent::Relative,
+    ))
+}
+
+/// Creates an encoder with basic info and sRGB color encoding set.
+pub fn srgb_encoder(basic_info: &jexcel::BasicInfo) -> jexcel::Result<jexcel::JxlEncoder> {
+    let mut encoder = jexcel::JxlEncoder::new().expect("failed to create encoder");
+    set_srgb(&mut encoder, basic_info)?;
+    Ok(encoder)
+}
+
+/// Pulls all output of the encoder.
+pub fn collect_output(encoder: &mut jexcel::JxlEncoder) -> jexcel::Result<Vec<u8>> {
+    let mut output = Vec::new();
+    for chunk in encoder.output_chunks() {
+        output.extend_from_slice(&chunk?);
+    }
+    Ok(output)
+}
+
+/// Encodes 8-bit sRGB frames using the given encoder, with frame settings configured by `f`.
+pub fn encode_rgb_with<'buf>(
+    encoder: &mut jexcel::JxlEncoder,
+    basic_info: &jexcel::BasicInfo,
+    frames: impl IntoIterator<Item = &'buf [u8]>,
+    f: impl FnOnce(&mut jexcel::FrameSettings<'_>) -> jexcel::Result<()>,
+) -> jexcel::Result<Vec<u8>> {
+    set_srgb(encoder, basic_info)?;
+    let settings = encoder.create_frame_settings_with(f)?;
+    encoder.add_image_frames(settings, 3, jexcel::SampleFormat::U8, frames)?;
+    collect_output(encoder)
+}
+
+/// Encodes 8-bit sRGB frames, with frame settings configured by `f`.
+pub fn encode_rgb<'buf>(
+    basic_info: &jexcel::BasicInfo,
+    frames: impl IntoIterator<Item = &'buf [u8]>,
+    f: impl FnOnce(&mut jexcel::FrameSettings<'_>) -> jexcel::Result<()>,
+) -> jexcel::Result<Vec<u8>> {
+    let mut encoder = jexcel::JxlEncoder::new().expect("failed to create encoder");
+    encode_rgb_with(&mut encoder, basic_info, frames, f)
+}
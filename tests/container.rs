@@ -0,0 +1,21 @@
+mod common;
+
+fn encode_1x1(use_container: bool) -> jexcel::Result<Vec<u8>> {
+    let mut encoder = jexcel::JxlEncoder::new().expect("failed to create encoder");
+    encoder.use_container(use_container)?;
+    common::encode_rgb_with(
+        &mut encoder,
+        &common::basic_info(1, 1),
+        [&[0u8, 0, 0][..]],
+        |_| Ok(()),
+    )
+}
+
+#[test]
+fn container_or_codestream() {
+    let jxl = encode_1x1(true).expect("failed to encode image");
+    assert_eq!(jexcel::check_signature(&jxl), jexcel::Signature::Container);
+
+    let jxl = encode_1x1(false).expect("failed to encode image");
+    assert_eq!(jexcel::check_signature(&jxl), jexcel::Signature::Codestream);
+}
@@ -0,0 +1,267 @@
+fn encode_rgb(width: u32, height: u32, rgb: &[u8]) -> jexcel::Result<Vec<u8>> {
+    let options = jexcel::EncodeOptions {
+        lossless: true,
+        ..Default::default()
+    };
+    jexcel::encode_rgb8(width, height, rgb, &options)
+}
+
+#[test]
+fn num_channels_mismatch() {
+    let (width, height) = (8u32, 8u32);
+    let rgb = image::RgbImage::from_fn(width, height, |x, y| {
+        image::Rgb([(x * 32) as u8, (y * 32) as u8, 64])
+    })
+    .into_raw();
+    let jxl = encode_rgb(width, height, &rgb).expect("failed to encode image");
+
+    let mut decoder = jexcel::JxlDecoder::new().expect("failed to create decoder");
+    for num_channels in [0, 1, 2, 5] {
+        assert!(
+            matches!(
+                decoder.decode_to_pixels(&jxl, num_channels, jexcel::SampleFormat::U8),
+                Err(jexcel::Error::ApiUsage)
+            ),
+            "{num_channels} channels should be rejected"
+        );
+    }
+
+    // Alpha is added as opaque.
+    let rgba = decoder
+        .decode_to_pixels(&jxl, 4, jexcel::SampleFormat::U8)
+        .expect("failed to decode image");
+    let expected = rgb
+        .chunks_exact(3)
+        .flat_map(|pixel| [pixel[0], pixel[1], pixel[2], 255])
+        .collect::<Vec<_>>();
+    assert!(rgba == expected, "output pixel mismatch");
+}
+
+#[test]
+fn planar_input() {
+    let (width, height) = (8u32, 6u32);
+    let num_pixels = (width * height) as usize;
+    let planes = (0..3u32)
+        .map(|channel| {
+            (0..num_pixels)
+                .map(|idx| idx as f32 / num_pixels as f32 * (channel + 1) as f32 / 3.)
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    let mut encoder = jexcel::JxlEncoder::new().expect("failed to create encoder");
+    let mut basic_info = jexcel::BasicInfo::new();
+    basic_info.xsize = width;
+    basic_info.ysize = height;
+    basic_info.set_uses_original_profile(true);
+    basic_info.set_float_depth(32, 8).unwrap();
+    encoder.set_basic_info(&basic_info).unwrap();
+    encoder
+        .set_color_encoding(&jexcel::ColorEncoding::srgb_linear(
+            jexcel::RenderingIntent::Relative,
+        ))
+        .unwrap();
+
+    let settings = encoder
+        .create_frame_settings_with(|settings| {
+            settings.lossless(true)?;
+            Ok(())
+        })
+        .unwrap();
+    let planes_ref = planes.iter().map(Vec::as_slice).collect::<Vec<_>>();
+    encoder
+        .add_frame(settings)
+        .unwrap()
+        .color_channels_planar_typed(&planes_ref)
+        .expect("failed to add planar frame");
+    encoder.close_input();
+
+    let mut jxl = Vec::new();
+    for chunk in encoder.output_chunks() {
+        jxl.extend_from_slice(&chunk.unwrap());
+    }
+
+    let mut decoder = jexcel::JxlDecoder::new().expect("failed to create decoder");
+    let pixels = decoder
+        .decode_to_pixels(&jxl, 3, jexcel::SampleFormat::F32)
+        .expect("failed to decode image");
+    let expected = (0..num_pixels)
+        .flat_map(|idx| planes.iter().map(move |plane| plane[idx]))
+        .flat_map(f32::to_ne_bytes)
+        .collect::<Vec<_>>();
+    assert!(pixels == expected, "output pixel mismatch");
+}
+
+#[test]
+fn planar_size_mismatch() {
+    let mut encoder = jexcel::JxlEncoder::new().expect("failed to create encoder");
+    let mut basic_info = jexcel::BasicInfo::new();
+    basic_info.xsize = 4;
+    basic_info.ysize = 4;
+    encoder.set_basic_info(&basic_info).unwrap();
+
+    let settings = encoder.create_frame_settings_with(|_| Ok(())).unwrap();
+    let (full, short) = (vec![0u8; 16], vec![0u8; 12]);
+    let result = encoder
+        .add_frame(settings)
+        .unwrap()
+        .color_channels_planar(jexcel::SampleFormat::U8, &[&full, &full, &short])
+        .map(|_| ());
+    assert!(matches!(
+        result,
+        Err(jexcel::Error::BufferSizeMismatch {
+            expected: 16,
+            got: 12
+        })
+    ));
+}
+
+#[test]
+fn image_out_bit_depth() {
+    let (width, height) = (32u32, 32u32);
+    let samples = (0..width * height * 3)
+        .map(|idx| (idx % 1024) as u16)
+        .collect::<Vec<_>>();
+    let full_range = samples
+        .iter()
+        .map(|&x| (x as f64 * 65535. / 1023.).round() as u16)
+        .collect::<Vec<_>>();
+
+    let mut encoder = jexcel::JxlEncoder::new().expect("failed to create encoder");
+    let mut basic_info = jexcel::BasicInfo::new();
+    basic_info.xsize = width;
+    basic_info.ysize = height;
+    basic_info.bits_per_sample = 10;
+    basic_info.set_uses_original_profile(true);
+    encoder.set_basic_info(&basic_info).unwrap();
+    encoder
+        .set_color_encoding(&jexcel::ColorEncoding::srgb(
+            jexcel::RenderingIntent::Relative,
+        ))
+        .unwrap();
+
+    let settings = encoder
+        .create_frame_settings_with(|settings| {
+            settings.lossless(true)?;
+            Ok(())
+        })
+        .unwrap();
+    encoder
+        .add_frame(settings)
+        .unwrap()
+        .color_channels_typed(3, &full_range)
+        .unwrap();
+    encoder.close_input();
+
+    let mut jxl = Vec::new();
+    for chunk in encoder.output_chunks() {
+        jxl.extend_from_slice(&chunk.unwrap());
+    }
+
+    let to_u16 = |buffer: Vec<u8>| {
+        buffer
+            .chunks_exact(2)
+            .map(|sample| u16::from_ne_bytes([sample[0], sample[1]]))
+            .collect::<Vec<_>>()
+    };
+
+    let mut decoder = jexcel::JxlDecoder::new().expect("failed to create decoder");
+    let pixels = decoder
+        .decode_to_pixels(&jxl, 3, jexcel::SampleFormat::U16)
+        .expect("failed to decode image");
+    assert!(to_u16(pixels) == full_range, "full range output mismatch");
+
+    decoder.set_image_out_bit_depth(jexcel::BitDepth::FromCodestream);
+    let pixels = decoder
+        .decode_to_pixels(&jxl, 3, jexcel::SampleFormat::U16)
+        .expect("failed to decode image");
+    assert!(to_u16(pixels) == samples, "10-bit output mismatch");
+
+    assert!(matches!(
+        decoder.decode_to_pixels(&jxl, 3, jexcel::SampleFormat::F32),
+        Err(jexcel::Error::ApiUsage)
+    ));
+}
+
+#[test]
+fn output_buffer_size() {
+    let (width, height) = (10u32, 6u32);
+    let rgb = vec![128u8; (width * height * 3) as usize];
+    let jxl = encode_rgb(width, height, &rgb).expect("failed to encode image");
+
+    let mut decoder = jexcel::JxlDecoder::new().expect("failed to create decoder");
+    let pixel_format = jexcel::PixelFormat::new(4, jexcel::SampleFormat::U16);
+    let size = decoder
+        .output_buffer_size(&jxl, &pixel_format)
+        .expect("failed to query buffer size");
+    assert_eq!(size, (width * height * 4 * 2) as usize);
+
+    let pixels = decoder
+        .decode_to_pixels_with_format(&jxl, &pixel_format)
+        .expect("failed to decode image");
+    assert_eq!(pixels.len(), size);
+
+    assert!(matches!(
+        decoder.output_buffer_size(&jxl[..4], &pixel_format),
+        Err(jexcel::Error::TruncatedInput)
+    ));
+}
+
+#[test]
+fn resampled_image_is_upsampled() {
+    let (width, height) = (32u32, 32u32);
+    let (small_width, small_height) = (width / 2, height / 2);
+    let rgb = image::RgbImage::from_fn(small_width, small_height, |x, y| {
+        image::Rgb([(x * 16) as u8, (y * 16) as u8, 128])
+    })
+    .into_raw();
+
+    let mut encoder = jexcel::JxlEncoder::new().expect("failed to create encoder");
+    let mut basic_info = jexcel::BasicInfo::new();
+    basic_info.xsize = width;
+    basic_info.ysize = height;
+    encoder.set_basic_info(&basic_info).unwrap();
+    encoder
+        .set_color_encoding(&jexcel::ColorEncoding::srgb(
+            jexcel::RenderingIntent::Relative,
+        ))
+        .unwrap();
+
+    let settings = encoder
+        .create_frame_settings_with(|settings| {
+            settings
+                .resampling(Some(2))?
+                .already_downsampled(Some(true));
+            Ok(())
+        })
+        .unwrap();
+    encoder
+        .add_image_frames(settings, 3, jexcel::SampleFormat::U8, [&*rgb])
+        .expect("failed to add downsampled frame");
+
+    let mut jxl = Vec::new();
+    for chunk in encoder.output_chunks() {
+        jxl.extend_from_slice(&chunk.unwrap());
+    }
+
+    let mut decoder = jexcel::JxlDecoder::new().expect("failed to create decoder");
+    let pixels = decoder
+        .decode_to_pixels(&jxl, 3, jexcel::SampleFormat::U8)
+        .expect("failed to decode image");
+    assert_eq!(pixels.len(), (width * height * 3) as usize);
+}
+
+#[test]
+fn float_depth_validation() {
+    let mut basic_info = jexcel::BasicInfo::new();
+    for (bits_per_sample, exponent_bits) in [(32, u32::MAX), (32, 1), (32, 9), (7, 5), (33, 8)] {
+        assert!(matches!(
+            basic_info.set_float_depth(bits_per_sample, exponent_bits),
+            Err(jexcel::Error::ApiUsage)
+        ));
+    }
+
+    basic_info.set_float_depth(16, 5).unwrap();
+    assert_eq!(basic_info.bits_per_sample, 16);
+    assert_eq!(basic_info.exponent_bits_per_sample, 5);
+}
@@ -0,0 +1,103 @@
+fn gradient(width: u32, height: u32) -> Vec<u8> {
+    (0..height)
+        .flat_map(|y| {
+            (0..width).flat_map(move |x| {
+                [
+                    (x * 255 / width) as u8,
+                    (y * 255 / height) as u8,
+                    ((x ^ y) * 5) as u8,
+                ]
+            })
+        })
+        .collect()
+}
+
+fn decode(jxl: &[u8]) -> Vec<u8> {
+    let mut decoder = jexcel::JxlDecoder::new().expect("failed to create decoder");
+    decoder
+        .decode_to_pixels(jxl, 3, jexcel::SampleFormat::U8)
+        .expect("failed to decode image")
+}
+
+#[test]
+fn lossless_roundtrip() {
+    let (width, height) = (48u32, 32u32);
+    let rgb = gradient(width, height);
+    let options = jexcel::EncodeOptions {
+        lossless: true,
+        ..Default::default()
+    };
+    let jxl = jexcel::encode_rgb8(width, height, &rgb, &options).expect("failed to encode image");
+
+    let mut decoder = jexcel::JxlDecoder::new().expect("failed to create decoder");
+    let basic_info = decoder.basic_info(&jxl).expect("failed to read basic info");
+    assert_eq!((basic_info.xsize, basic_info.ysize), (width, height));
+    assert_ne!(basic_info.uses_original_profile, 0);
+    assert!(decode(&jxl) == rgb, "output pixel mismatch");
+}
+
+#[test]
+fn larger_distance_is_smaller() {
+    let (width, height) = (128u32, 96u32);
+    let rgb = gradient(width, height);
+
+    let sizes = [0.5f32, 2., 8.].map(|distance| {
+        let options = jexcel::EncodeOptions {
+            distance,
+            ..Default::default()
+        };
+        let jxl =
+            jexcel::encode_rgb8(width, height, &rgb, &options).expect("failed to encode image");
+        assert_eq!(decode(&jxl).len(), rgb.len());
+        jxl.len()
+    });
+    assert!(
+        sizes.is_sorted_by(|a, b| a > b),
+        "sizes should decrease as distance increases: {sizes:?}"
+    );
+}
+
+#[test]
+fn effort_is_lossless() {
+    let (width, height) = (32u32, 32u32);
+    let rgb = gradient(width, height);
+
+    for effort in [
+        jexcel::Effort::Lightning,
+        jexcel::Effort::Squirrel,
+        jexcel::Effort::Tortoise,
+    ] {
+        let options = jexcel::EncodeOptions {
+            lossless: true,
+            effort,
+            ..Default::default()
+        };
+        let jxl =
+            jexcel::encode_rgb8(width, height, &rgb, &options).expect("failed to encode image");
+        assert!(decode(&jxl) == rgb, "output pixel mismatch with {effort:?}");
+    }
+}
+
+#[test]
+fn invalid_options() {
+    let rgb = gradient(4, 4);
+    assert!(matches!(
+        jexcel::encode_rgb8(4, 5, &rgb, &Default::default()),
+        Err(jexcel::Error::BufferSizeMismatch {
+            expected: 60,
+            got: 48
+        })
+    ));
+
+    // Distance doesn't trigger lossless encoding by itself.
+    for distance in [30., 0., -1.] {
+        let options = jexcel::EncodeOptions {
+            distance,
+            ..Default::default()
+        };
+        assert!(matches!(
+            jexcel::encode_rgb8(4, 4, &rgb, &options),
+            Err(jexcel::Error::ApiUsage)
+        ));
+    }
+}
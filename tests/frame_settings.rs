@@ -0,0 +1,143 @@
+mod common;
+
+#[test]
+fn values_are_recorded() {
+    let mut encoder = jexcel::JxlEncoder::new().expect("failed to create encoder");
+    let settings = encoder
+        .create_frame_settings_with(|settings| {
+            settings
+                .effort(jexcel::Effort::Kitten)
+                .distance(2.5)?
+                .modular(Some(true))
+                .extra_channel_distance(0, Some(0.))?
+                .extra_channel_distance(1, Some(3.))?;
+            Ok(())
+        })
+        .unwrap();
+
+    let values = encoder.frame_settings_values(settings).unwrap();
+    assert_eq!(values.effort(), Some(jexcel::Effort::Kitten));
+    assert_eq!(values.distance(), Some(2.5));
+    assert_eq!(values.modular(), Some(true));
+    assert_eq!(values.extra_channel_distance(0), Some(0.));
+    assert_eq!(values.extra_channel_distance(1), Some(3.));
+
+    let cloned = encoder
+        .clone_modify_frame_settings_with(settings, |settings| {
+            settings.lossless(true)?.extra_channel_distance(1, None)?;
+            Ok(())
+        })
+        .unwrap();
+
+    let values = encoder.frame_settings_values(cloned).unwrap();
+    assert_eq!(values.effort(), Some(jexcel::Effort::Kitten));
+    assert_eq!(values.distance(), None);
+    assert!(values.lossless());
+    assert_eq!(values.extra_channel_distance(0), Some(0.));
+    assert_eq!(values.extra_channel_distance(1), None);
+
+    let result = encoder.update_frame_settings_with(settings, |settings| {
+        settings.extra_channel_distance(0, Some(-1.))?;
+        Ok(())
+    });
+    assert!(matches!(result, Err(jexcel::Error::ApiUsage)));
+}
+
+#[test]
+fn lossless_requires_original_profile() {
+    let mut encoder = jexcel::JxlEncoder::new().expect("failed to create encoder");
+
+    let mut basic_info = jexcel::BasicInfo::new();
+    basic_info.xsize = 1;
+    basic_info.ysize = 1;
+    basic_info.set_uses_original_profile(false);
+    encoder.set_basic_info(&basic_info).unwrap();
+
+    let settings = encoder
+        .create_frame_settings_with(|settings| {
+            settings.lossless(true)?;
+            Ok(())
+        })
+        .unwrap();
+    let result = encoder
+        .add_frame(settings)
+        .unwrap()
+        .color_channels(3, jexcel::SampleFormat::U8, &[0, 0, 0])
+        .map(|_| ());
+    assert!(matches!(result, Err(jexcel::Error::ApiUsage)));
+}
+
+fn encode_rgb_with(
+    width: u32,
+    height: u32,
+    rgb: &[u8],
+    f: impl FnOnce(&mut jexcel::FrameSettings<'_>) -> jexcel::Result<()>,
+) -> Vec<u8> {
+    common::encode_rgb(&common::basic_info(width, height), [rgb], f)
+        .expect("failed to encode image")
+}
+
+#[test]
+fn near_lossless() {
+    let (width, height) = (16u32, 16u32);
+    let rgb = (0..width * height * 3)
+        .map(|idx| (idx * 37 % 256) as u8)
+        .collect::<Vec<_>>();
+    let decode = |jxl: &[u8]| {
+        let mut decoder = jexcel::JxlDecoder::new().expect("failed to create decoder");
+        decoder
+            .decode_to_pixels(jxl, 3, jexcel::SampleFormat::U8)
+            .expect("failed to decode image")
+    };
+
+    let jxl = encode_rgb_with(width, height, &rgb, |settings| {
+        settings.near_lossless(2)?;
+        Ok(())
+    });
+    let decoded = decode(&jxl);
+    assert_eq!(decoded.len(), rgb.len());
+    for (&a, &b) in decoded.iter().zip(&rgb) {
+        assert!(a.abs_diff(b) <= 2, "error exceeds delta: {a} vs {b}");
+    }
+
+    // Pixels are added as is once near-lossless mode is disabled.
+    let jxl = encode_rgb_with(width, height, &rgb, |settings| {
+        settings.near_lossless(2)?.lossless(false)?.lossless(true)?;
+        Ok(())
+    });
+    assert!(decode(&jxl) == rgb, "pixels should not be quantized");
+
+    let mut encoder = jexcel::JxlEncoder::new().expect("failed to create encoder");
+    let settings = encoder
+        .create_frame_settings_with(|settings| {
+            settings.near_lossless(2)?;
+            Ok(())
+        })
+        .unwrap();
+    assert_eq!(
+        encoder
+            .frame_settings_values(settings)
+            .unwrap()
+            .near_lossless(),
+        2
+    );
+
+    encoder
+        .update_frame_settings_with(settings, |settings| {
+            settings.lossless(false)?.distance(1.)?;
+            Ok(())
+        })
+        .unwrap();
+    let values = encoder.frame_settings_values(settings).unwrap();
+    assert_eq!(values.near_lossless(), 0);
+    assert_eq!(values.distance(), Some(1.));
+
+    encoder
+        .update_frame_settings_with(settings, |settings| {
+            settings.near_lossless(2)?.modular(Some(false));
+            Ok(())
+        })
+        .unwrap();
+    let values = encoder.frame_settings_values(settings).unwrap();
+    assert_eq!(values.near_lossless(), 0);
+}
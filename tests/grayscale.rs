@@ -0,0 +1,61 @@
+use image::DynamicImage;
+
+fn roundtrip(image: &DynamicImage) -> DynamicImage {
+    let options = jexcel::EncodeOptions {
+        lossless: true,
+        ..Default::default()
+    };
+    let jxl = jexcel::encode_dynamic_image(image, &options).expect("failed to encode image");
+
+    let mut decoder = jexcel::JxlDecoder::new().expect("failed to create decoder");
+    let basic_info = decoder.basic_info(&jxl).expect("failed to read basic info");
+    assert_eq!(basic_info.num_color_channels, 1);
+    assert_ne!(basic_info.alpha_bits, 0);
+
+    jexcel::decode_to_dynamic_image(&jxl).expect("failed to decode image")
+}
+
+#[test]
+fn gray_alpha_8bit_roundtrip() {
+    let image = image::GrayAlphaImage::from_fn(16, 12, |x, y| {
+        image::LumaA([(x * 16) as u8, (y * 20) as u8])
+    });
+    let image = DynamicImage::from(image);
+
+    let decoded = roundtrip(&image);
+    assert_eq!(decoded.color(), image::ColorType::La8);
+    assert!(
+        decoded.as_bytes() == image.as_bytes(),
+        "output pixel mismatch"
+    );
+}
+
+#[test]
+fn gray_alpha_16bit_roundtrip() {
+    let image = image::ImageBuffer::<image::LumaA<u16>, _>::from_fn(16, 12, |x, y| {
+        image::LumaA([(x * 4096) as u16, (y * 5000) as u16])
+    });
+    let image = DynamicImage::from(image);
+
+    let decoded = roundtrip(&image);
+    assert_eq!(decoded.color(), image::ColorType::La16);
+    assert!(
+        decoded.as_bytes() == image.as_bytes(),
+        "output pixel mismatch"
+    );
+}
+
+#[test]
+fn num_color_channels_validation() {
+    let mut basic_info = jexcel::BasicInfo::new();
+    for num_color_channels in [0, 2, 4] {
+        assert!(matches!(
+            basic_info.set_num_color_channels(num_color_channels),
+            Err(jexcel::Error::ApiUsage)
+        ));
+    }
+    assert_eq!(basic_info.num_color_channels, 3);
+
+    basic_info.set_num_color_channels(1).unwrap();
+    assert_eq!(basic_info.num_color_channels, 1);
+}
@@ -0,0 +1,83 @@
+mod common;
+
+fn transcode(jpeg: &[u8]) -> jexcel::Result<Vec<u8>> {
+    let mut encoder = jexcel::JxlEncoder::new().expect("failed to create encoder");
+    encoder.set_jpeg_reconstruction(true)?;
+
+    let settings = encoder.create_frame_settings_with(|_| Ok(()))?;
+    encoder.add_frame(settings)?.jpeg(jpeg)?;
+    encoder.close_input();
+
+    common::collect_output(&mut encoder)
+}
+
+fn assert_round_trip(jpeg: &[u8]) {
+    let jxl = transcode(jpeg).expect("failed to transcode JPEG");
+
+    let mut decoder = jexcel::JxlDecoder::new().expect("failed to create decoder");
+    assert!(
+        decoder
+            .has_jpeg_reconstruction(&jxl)
+            .expect("failed to read image")
+    );
+    let reconstructed = decoder
+        .decode_to_jpeg(&jxl)
+        .expect("failed to reconstruct JPEG");
+
+    assert!(reconstructed == jpeg, "JPEG bitstream mismatch");
+}
+
+#[test]
+fn baseline_round_trip() {
+    let (width, height) = (64u32, 48u32);
+    let image = image::RgbImage::from_fn(width, height, |x, y| {
+        image::Rgb([(x * 4) as u8, (y * 5) as u8, ((x ^ y) * 3) as u8])
+    });
+
+    let mut jpeg = Vec::new();
+    image::codecs::jpeg::JpegEncoder::new_with_quality(&mut jpeg, 90)
+        .encode_image(&image)
+        .expect("failed to encode baseline JPEG");
+
+    assert_round_trip(&jpeg);
+}
+
+#[test]
+fn progressive_round_trip() {
+    let jpeg = include_bytes!("data/progressive.jpg");
+    assert_round_trip(jpeg);
+}
+
+#[test]
+fn pixels_without_reconstruction() {
+    let mut encoder = jexcel::JxlEncoder::new().expect("failed to create encoder");
+    let mut basic_info = jexcel::BasicInfo::new();
+    basic_info.xsize = 1;
+    basic_info.ysize = 1;
+    encoder.set_basic_info(&basic_info).unwrap();
+    encoder
+        .set_color_encoding(&jexcel::ColorEncoding::srgb(
+            jexcel::RenderingIntent::Relative,
+        ))
+        .unwrap();
+
+    let settings = encoder.create_frame_settings_with(|_| Ok(())).unwrap();
+    encoder
+        .add_frame(settings)
+        .unwrap()
+        .color_channels(3, jexcel::SampleFormat::U8, &[0, 0, 0])
+        .unwrap();
+    encoder.close_input();
+
+    let mut jxl = Vec::new();
+    for chunk in encoder.output_chunks() {
+        jxl.extend_from_slice(&chunk.unwrap());
+    }
+
+    let mut decoder = jexcel::JxlDecoder::new().expect("failed to create decoder");
+    assert!(
+        !decoder
+            .has_jpeg_reconstruction(&jxl)
+            .expect("failed to read image")
+    );
+}
@@ -0,0 +1,91 @@
+mod common;
+
+fn encode_with_boxes(boxes: &[jexcel::MetadataBox]) -> jexcel::Result<Vec<u8>> {
+    let mut encoder = jexcel::JxlEncoder::new().expect("failed to create encoder");
+    encoder.use_boxes()?;
+    for metadata_box in boxes {
+        encoder.add_box(metadata_box)?;
+    }
+    encoder.close_boxes();
+
+    common::encode_rgb_with(
+        &mut encoder,
+        &common::basic_info(1, 1),
+        [&[0u8, 0, 0][..]],
+        |_| Ok(()),
+    )
+}
+
+#[test]
+fn boxes_roundtrip() {
+    // Exif boxes start with the offset to the TIFF header.
+    let exif = jexcel::MetadataBox::new(*b"Exif", b"\0\0\0\0MM\0\x2a\0\0\0\x08".to_vec());
+    // Highly compressible, so that decompressed contents overflow the initial buffer sized by
+    // the compressed box.
+    let xmp_contents = "<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"></x:xmpmeta>\n"
+        .repeat(4096)
+        .into_bytes();
+    let mut xmp = jexcel::MetadataBox::new(*b"xml ", xmp_contents);
+    xmp.set_compressed(true);
+    let unknown = jexcel::MetadataBox::new(*b"abcd", (0..=255u8).collect());
+
+    let expected = [exif, xmp, unknown];
+    let jxl = encode_with_boxes(&expected).expect("failed to encode image");
+    assert!(
+        jxl.len() < expected[1].contents().len(),
+        "xml box should be compressed"
+    );
+
+    let mut decoder = jexcel::JxlDecoder::new().expect("failed to create decoder");
+    let boxes = decoder.boxes(&jxl).expect("failed to read boxes");
+    assert_eq!(boxes.len(), expected.len());
+    for (actual, expected) in boxes.iter().zip(&expected) {
+        assert_eq!(actual.box_type(), expected.box_type());
+        assert_eq!(actual.is_compressed(), expected.is_compressed());
+        assert!(
+            actual.contents() == expected.contents(),
+            "contents of {:?} box mismatch",
+            String::from_utf8_lossy(&expected.box_type()),
+        );
+    }
+}
+
+#[test]
+fn recompress_copies_boxes_verbatim() {
+    let exif = jexcel::MetadataBox::new(*b"Exif", b"\0\0\0\0MM\0\x2a\0\0\0\x08".to_vec());
+    let mut xmp = jexcel::MetadataBox::new(
+        *b"xml ",
+        b"<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"></x:xmpmeta>".to_vec(),
+    );
+    xmp.set_compressed(true);
+    let jumbf = jexcel::MetadataBox::new(*b"jumb", b"\0\0\0\x11jumd".to_vec());
+    let mut unknown = jexcel::MetadataBox::new(*b"abcd", (0..=255u8).collect());
+    unknown.set_compressed(true);
+
+    let jxl = encode_with_boxes(&[exif, xmp, jumbf, unknown]).expect("failed to encode image");
+    let options = jexcel::EncodeOptions {
+        lossless: true,
+        ..Default::default()
+    };
+    let recompressed = jexcel::recompress(&jxl, &options).expect("failed to recompress image");
+
+    let mut decoder = jexcel::JxlDecoder::new().expect("failed to create decoder");
+    let expected = decoder.raw_boxes(&jxl).expect("failed to read boxes");
+    let types = expected.iter().map(|b| b.box_type()).collect::<Vec<_>>();
+    assert_eq!(types, [*b"Exif", *b"brob", *b"jumb", *b"brob"]);
+    assert!(expected[1].contents().starts_with(b"xml "));
+    assert!(expected[3].contents().starts_with(b"abcd"));
+
+    let actual = decoder
+        .raw_boxes(&recompressed)
+        .expect("failed to read boxes");
+    assert_eq!(actual, expected);
+
+    let expected = decoder
+        .decode_to_pixels(&jxl, 3, jexcel::SampleFormat::U8)
+        .expect("failed to decode image");
+    let actual = decoder
+        .decode_to_pixels(&recompressed, 3, jexcel::SampleFormat::U8)
+        .expect("failed to decode image");
+    assert!(actual == expected, "output pixel mismatch");
+}
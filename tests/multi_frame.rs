@@ -0,0 +1,160 @@
+mod common;
+
+fn encode_frames(width: u32, height: u32, frames: &[Vec<u8>]) -> jexcel::Result<Vec<u8>> {
+    common::encode_rgb(
+        &common::basic_info(width, height),
+        frames.iter().map(Vec::as_slice),
+        |settings| {
+            settings.lossless(true)?;
+            Ok(())
+        },
+    )
+}
+
+#[test]
+fn three_frames() {
+    let (width, height) = (32u32, 24u32);
+    let frames = (0..3u32)
+        .map(|idx| {
+            let image = image::RgbImage::from_fn(width, height, |x, y| {
+                image::Rgb([(x * 8) as u8, (y * 10) as u8, (idx * 100) as u8])
+            });
+            image.into_raw()
+        })
+        .collect::<Vec<_>>();
+
+    let jxl = encode_frames(width, height, &frames).expect("failed to encode frames");
+
+    let mut decoder = jexcel::JxlDecoder::new().expect("failed to create decoder");
+    let pixels = decoder
+        .decode_to_pixels(&jxl, 3, jexcel::SampleFormat::U8)
+        .expect("failed to decode image");
+
+    // Frames without animation are composited, and replaced by the last frame.
+    assert!(pixels == frames[2], "output pixel mismatch");
+}
+
+#[test]
+fn pull_before_close_is_rejected() {
+    let mut encoder = jexcel::JxlEncoder::new().expect("failed to create encoder");
+
+    let mut basic_info = jexcel::BasicInfo::new();
+    basic_info.xsize = 1;
+    basic_info.ysize = 1;
+    encoder.set_basic_info(&basic_info).unwrap();
+    encoder
+        .set_color_encoding(&jexcel::ColorEncoding::srgb(
+            jexcel::RenderingIntent::Relative,
+        ))
+        .unwrap();
+
+    let settings = encoder.create_frame_settings_with(|_| Ok(())).unwrap();
+    encoder
+        .add_frame(settings)
+        .unwrap()
+        .color_channels(3, jexcel::SampleFormat::U8, &[0, 0, 0])
+        .unwrap();
+
+    let mut buffer = vec![0u8; 1024];
+    assert!(matches!(
+        encoder.pull_outputs(&mut buffer),
+        Err(jexcel::Error::ApiUsage)
+    ));
+}
+
+fn encode_animation(width: u32, height: u32, frames: &[Vec<u8>]) -> jexcel::Result<Vec<u8>> {
+    let mut basic_info = common::basic_info(width, height);
+    basic_info.set_animation(1000, 1, 0)?;
+
+    let frame_header = jexcel::FrameHeader::builder().duration(100).build()?;
+    common::encode_rgb(&basic_info, frames.iter().map(Vec::as_slice), |settings| {
+        settings.lossless(true)?.frame_header(&frame_header)?;
+        Ok(())
+    })
+}
+
+#[test]
+fn decode_frame_by_index() {
+    let (width, height) = (16u32, 16u32);
+    let frames = (0..3u32)
+        .map(|idx| vec![(idx * 100) as u8; (width * height * 3) as usize])
+        .collect::<Vec<_>>();
+
+    let jxl = encode_animation(width, height, &frames).expect("failed to encode animation");
+
+    let mut decoder = jexcel::JxlDecoder::new().expect("failed to create decoder");
+    for (idx, frame) in frames.iter().enumerate() {
+        let pixels = decoder
+            .decode_frame(&jxl, idx as u32, 3, jexcel::SampleFormat::U8)
+            .expect("failed to decode frame");
+        assert!(pixels == *frame, "output pixel mismatch of frame {idx}");
+    }
+
+    assert!(matches!(
+        decoder.decode_frame(&jxl, 3, 3, jexcel::SampleFormat::U8),
+        Err(jexcel::Error::ApiUsage)
+    ));
+}
+
+#[test]
+fn small_output_chunks() {
+    let (width, height) = (16u32, 16u32);
+    let frame = image::RgbImage::from_fn(width, height, |x, y| {
+        image::Rgb([(x * 16) as u8, (y * 16) as u8, 0])
+    })
+    .into_raw();
+
+    let mut encoder = jexcel::JxlEncoder::new().expect("failed to create encoder");
+    let mut basic_info = jexcel::BasicInfo::new();
+    basic_info.xsize = width;
+    basic_info.ysize = height;
+    encoder.set_basic_info(&basic_info).unwrap();
+    encoder
+        .set_color_encoding(&jexcel::ColorEncoding::srgb(
+            jexcel::RenderingIntent::Relative,
+        ))
+        .unwrap();
+
+    let settings = encoder.create_frame_settings_with(|_| Ok(())).unwrap();
+    encoder
+        .add_image_frames(settings, 3, jexcel::SampleFormat::U8, [&*frame])
+        .unwrap();
+
+    let mut output = Vec::new();
+    for chunk in encoder.output_chunks_with_size(0) {
+        let chunk = chunk.expect("failed to pull output");
+        assert!(chunk.len() <= 32);
+        output.extend_from_slice(&chunk);
+    }
+
+    let mut decoder = jexcel::JxlDecoder::new().expect("failed to create decoder");
+    let basic_info = decoder
+        .basic_info(&output)
+        .expect("failed to decode output");
+    assert_eq!((basic_info.xsize, basic_info.ysize), (width, height));
+}
+
+#[test]
+fn frame_header_builder_validation() {
+    let header = jexcel::FrameHeader::builder()
+        .duration(10)
+        .layer(-4, 4, 8, 8)
+        .blend(jexcel::BlendMode::Blend, 1, 0)
+        .save_as_reference(1)
+        .build()
+        .expect("valid frame header is rejected");
+    assert_eq!(header.duration, 10);
+    assert_eq!(header.layer_info.crop_x0, -4);
+    assert_eq!(header.layer_info.save_as_reference, 1);
+
+    let invalid = [
+        jexcel::FrameHeader::builder().layer(0, 0, 0, 8).build(),
+        jexcel::FrameHeader::builder()
+            .blend(jexcel::BlendMode::Add, 4, 0)
+            .build(),
+        jexcel::FrameHeader::builder().save_as_reference(3).build(),
+    ];
+    for result in invalid {
+        assert!(matches!(result, Err(jexcel::Error::ApiUsage)));
+    }
+}
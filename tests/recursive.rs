@@ -0,0 +1,52 @@
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// Images waiting for the memory budget shouldn't block encoding of the image holding it.
+#[test]
+fn memory_limit_smaller_than_two_images() {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("recursive_memory_limit");
+    let (input_dir, output_dir) = (dir.join("input"), dir.join("output"));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&input_dir).unwrap();
+
+    let num_images = 8;
+    for idx in 0..num_images {
+        let image = image::RgbImage::from_fn(64, 64, |x, y| {
+            image::Rgb([(x * 4) as u8, (y * 4) as u8, (idx * 32) as u8])
+        });
+        image.save(input_dir.join(format!("{idx}.png"))).unwrap();
+    }
+
+    // Each image is estimated to use 64 * 64 * (3 + 3 * 4) = 61440 bytes.
+    let mut child = Command::new(env!("CARGO_BIN_EXE_jexcel"))
+        .arg("--recursive")
+        .args(["--jobs", "4", "--max-memory", "100000"])
+        .arg("--output")
+        .arg(&output_dir)
+        .arg(&input_dir)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("failed to run jexcel");
+
+    let begin = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait().unwrap() {
+            break status;
+        }
+        if begin.elapsed() > Duration::from_secs(120) {
+            child.kill().unwrap();
+            panic!("recursive encoding didn't finish in time");
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    };
+    assert!(status.success());
+
+    for idx in 0..num_images {
+        let jxl = std::fs::read(output_dir.join(format!("{idx}.jxl"))).expect("missing output");
+        let mut decoder = jexcel::JxlDecoder::new().expect("failed to create decoder");
+        let basic_info = decoder.basic_info(&jxl).expect("failed to read basic info");
+        assert_eq!((basic_info.xsize, basic_info.ysize), (64, 64));
+    }
+}
@@ -0,0 +1,51 @@
+mod common;
+
+fn encode_with_spot_color(
+    width: u32,
+    height: u32,
+    rgb: &[u8],
+    spot: &[u8],
+) -> jexcel::Result<Vec<u8>> {
+    let mut basic_info = common::basic_info(width, height);
+    basic_info.num_extra_channels = 1;
+    let mut encoder = common::srgb_encoder(&basic_info)?;
+    encoder.set_extra_channel_info(0, &jexcel::ExtraChannelInfo::spot_color([1., 0., 0., 1.]))?;
+
+    let settings = encoder.create_frame_settings_with(|settings| {
+        settings.lossless(true)?;
+        Ok(())
+    })?;
+    encoder
+        .add_frame(settings)?
+        .color_channels(3, jexcel::SampleFormat::U8, rgb)?
+        .extra_channel(
+            0,
+            &jexcel::PixelFormat::new(1, jexcel::SampleFormat::U8),
+            spot,
+        )?;
+    encoder.close_input();
+
+    common::collect_output(&mut encoder)
+}
+
+#[test]
+fn render_spot_colors_toggle() {
+    let (width, height) = (8u32, 8u32);
+    let rgb = vec![0u8; (width * height * 3) as usize];
+    let spot = vec![255u8; (width * height) as usize];
+
+    let jxl = encode_with_spot_color(width, height, &rgb, &spot).expect("failed to encode image");
+
+    let mut decoder = jexcel::JxlDecoder::new().expect("failed to create decoder");
+    decoder.set_render_spot_colors(false);
+    let pixels = decoder
+        .decode_to_pixels(&jxl, 3, jexcel::SampleFormat::U8)
+        .expect("failed to decode image");
+    assert!(pixels == rgb, "spot color should not be rendered");
+
+    decoder.set_render_spot_colors(true);
+    let pixels = decoder
+        .decode_to_pixels(&jxl, 3, jexcel::SampleFormat::U8)
+        .expect("failed to decode image");
+    assert!(pixels != rgb, "spot color should be rendered");
+}
@@ -0,0 +1,80 @@
+mod common;
+
+fn encode_noise(encoder: &mut jexcel::JxlEncoder, width: u32, height: u32) -> Vec<u8> {
+    let mut state = 0x1234_5678u32;
+    let pixels = (0..width * height * 3)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect::<Vec<_>>();
+
+    common::encode_rgb_with(
+        encoder,
+        &common::basic_info(width, height),
+        [&*pixels],
+        |settings| {
+            settings.distance(1.)?;
+            Ok(())
+        },
+    )
+    .expect("failed to encode image")
+}
+
+/// Output shouldn't depend on the number of threads, nor on how tasks are scheduled to them.
+#[test]
+fn output_is_deterministic() {
+    let (width, height) = (512u32, 384u32);
+
+    let mut encoder = jexcel::JxlEncoder::new_single_threaded().expect("failed to create encoder");
+    let expected = encode_noise(&mut encoder, width, height);
+
+    for num_threads in [1, 2, 3, 8] {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(num_threads)
+            .build()
+            .expect("failed to build thread pool");
+        for _ in 0..2 {
+            let output = pool.install(|| {
+                let mut encoder = jexcel::JxlEncoder::new().expect("failed to create encoder");
+                encode_noise(&mut encoder, width, height)
+            });
+            assert!(
+                output == expected,
+                "output differs with {num_threads} threads"
+            );
+        }
+    }
+}
+
+#[test]
+fn decoder_can_be_moved_across_threads() {
+    let (width, height) = (64u32, 48u32);
+    let mut encoder = jexcel::JxlEncoder::new().expect("failed to create encoder");
+    let jxl = encode_noise(&mut encoder, width, height);
+
+    let mut decoder = jexcel::JxlDecoder::new().expect("failed to create decoder");
+    let expected = decoder
+        .decode_to_pixels(&jxl, 3, jexcel::SampleFormat::U8)
+        .expect("failed to decode image");
+
+    let (mut decoder, pixels) = std::thread::scope(|scope| {
+        scope
+            .spawn(|| {
+                let pixels = decoder
+                    .decode_to_pixels(&jxl, 3, jexcel::SampleFormat::U8)
+                    .expect("failed to decode image");
+                (decoder, pixels)
+            })
+            .join()
+            .unwrap()
+    });
+    assert!(pixels == expected, "output differs in another thread");
+
+    let pixels = decoder
+        .decode_to_pixels(&jxl, 3, jexcel::SampleFormat::U8)
+        .expect("failed to decode image");
+    assert!(pixels == expected, "output differs after moving back");
+}